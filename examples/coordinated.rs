@@ -0,0 +1,69 @@
+use poisson_ticker::coordinator::{CoordinatedClient, Coordinator, CoordinatorConfig};
+use poisson_ticker::loadgen::{fixed_payload, run_load, LoadGenConfig};
+use poisson_ticker::transport::channel_echo;
+use std::time::Duration;
+
+type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+// Stands in for a separate client process.
+async fn client(coordinator: std::net::SocketAddr, name: String) -> Result<(), StdError> {
+    let client = CoordinatedClient::join(coordinator, &name).await?;
+    let assignment = client.assignment().clone();
+    println!("{}: {:?}", name, assignment);
+
+    client.wait_for_start().await;
+    let config = LoadGenConfig {
+        duration: assignment.duration,
+        warmup: Duration::from_millis(200),
+        cooldown: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let result = run_load(
+        &channel_echo(),
+        &config,
+        assignment.schedule()?,
+        fixed_payload(16),
+    )
+    .await?;
+    client
+        .upload(&result.stats, Some(&result.latencies))
+        .await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), StdError> {
+    let config = CoordinatorConfig {
+        clients: 3,
+        total_rate: 6000.,
+        duration: Duration::from_secs(2),
+        start_delay: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let coordinator = Coordinator::bind("127.0.0.1:0".parse()?, config).await?;
+    let addr = coordinator.local_addr()?;
+    let clients: Vec<_> = (0..3)
+        .map(|i| tokio::spawn(client(addr, format!("client-{}", i))))
+        .collect();
+
+    let result = coordinator.run().await?;
+    for c in clients {
+        c.await??;
+    }
+    for (c, map) in result.clients.iter().zip(result.aligned_latencies()?) {
+        let map = map.unwrap();
+        println!(
+            "{}: sent {}, first send {:?} after the earliest",
+            c.name,
+            c.stats.total_objects_sent,
+            map.first_send_time().unwrap() - map.anchor().unwrap().instant,
+        );
+    }
+    println!(
+        "merged: sent {}, achieved {:.0} req/s, p99 {:?}",
+        result.stats.total_objects_sent,
+        result.stats.throughput(),
+        Duration::from_nanos(result.stats.histogram.value_at_quantile(0.99)?),
+    );
+    Ok(())
+}
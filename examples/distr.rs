@@ -1,4 +1,5 @@
 use futures_util::stream::{Stream, StreamExt};
+use poisson_ticker::requests::{DistributionType, RequestSchedule};
 use std::time::{Duration, Instant};
 
 type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -23,15 +24,21 @@ async fn main() -> Result<(), StdError> {
 
     let mut f = std::fs::File::create("./distr.data")?;
     use std::io::Write;
-    write!(&mut f, "Ticker Target_us Actual_us\n")?;
+    writeln!(&mut f, "Ticker Target_us Actual_us")?;
 
     for d in durations {
-        let durs = do_ticks(poisson_ticker::SpinTicker::new(d)).await;
+        let rate_pps = 1_000_000_000.0 / d.as_nanos() as f64;
+        let schedule = RequestSchedule::new(1000, rate_pps, DistributionType::Exponential)?;
+        let durs = do_ticks(poisson_ticker::SpinTicker::new(
+            schedule,
+            Duration::from_secs(60),
+        ))
+        .await;
         let sum: Duration = durs.iter().sum();
         let mean: Duration = sum / durs.len() as u32;
         println!("spin mean: {:?} vs {:?}", mean, d);
         for o in durs {
-            write!(&mut f, "spin {} {}\n", d.as_micros(), o.as_micros())?;
+            writeln!(&mut f, "spin {} {}", d.as_micros(), o.as_micros())?;
         }
     }
 
@@ -0,0 +1,36 @@
+use poisson_ticker::loadgen::{fixed_payload, run_load, EchoServer, LoadGenConfig, Protocol};
+use poisson_ticker::requests::{DistributionType, RequestSchedule};
+use poisson_ticker::transport::{channel_echo, TcpTransport, Transport, UdpTransport};
+use std::time::Duration;
+
+type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+async fn run<T: Transport>(name: &str, transport: &T) -> Result<(), StdError> {
+    let config = LoadGenConfig {
+        connections: 4,
+        duration: Duration::from_secs(3),
+        ..Default::default()
+    };
+    let schedule = RequestSchedule::new(30_000, 10_000., DistributionType::Exponential)?;
+    let result = run_load(transport, &config, schedule, fixed_payload(64)).await?;
+    println!(
+        "{}: sent {} achieved {:.0} req/s, p50 {:?}, p99 {:?}, dropped {}",
+        name,
+        result.stats.total_objects_sent,
+        result.stats.throughput(),
+        Duration::from_nanos(result.stats.histogram.value_at_quantile(0.5)?),
+        Duration::from_nanos(result.stats.histogram.value_at_quantile(0.99)?),
+        result.stats.outcomes.dropped,
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), StdError> {
+    let udp = EchoServer::bind(Protocol::Udp, "127.0.0.1:0".parse()?).await?;
+    run("udp", &UdpTransport::new(udp.local_addr())).await?;
+    let tcp = EchoServer::bind(Protocol::Tcp, "127.0.0.1:0".parse()?).await?;
+    run("tcp", &TcpTransport::new(tcp.local_addr())).await?;
+    run("in-process", &channel_echo()).await?;
+    Ok(())
+}
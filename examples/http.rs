@@ -0,0 +1,58 @@
+use poisson_ticker::http::{
+    fixed_body, run_http_load, HttpLoadConfig, HttpTestServer, RequestTemplate, TestResponse,
+};
+use poisson_ticker::requests::{DistributionType, RequestSchedule};
+use std::time::Duration;
+
+type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[tokio::main]
+async fn main() -> Result<(), StdError> {
+    // one request in ten is a slow error
+    let server = HttpTestServer::bind_with("127.0.0.1:0".parse()?, |req| {
+        if req.body.first() == Some(&b'9') {
+            TestResponse {
+                status: 503,
+                body: vec![],
+                delay: Duration::from_millis(2),
+            }
+        } else {
+            TestResponse::ok(req.body.clone())
+        }
+    })
+    .await?;
+
+    let mut config = HttpLoadConfig::new(server.base_url());
+    config.connections = 8;
+    config.duration = Duration::from_secs(3);
+    let template = RequestTemplate::new("POST", "/echo").with_header("Content-Type", "text/plain");
+
+    let schedule = RequestSchedule::new(15_000, 5_000., DistributionType::Exponential)?;
+    let result = run_http_load(&config, schedule, &template, |id| {
+        (id % 10).to_string().into_bytes()
+    })
+    .await?;
+    println!(
+        "sent {} achieved {:.0} req/s, p50 {:?}, p99 {:?}, status codes {:?}",
+        result.stats.total_objects_sent,
+        result.stats.throughput(),
+        Duration::from_nanos(result.stats.histogram.value_at_quantile(0.5)?),
+        Duration::from_nanos(result.stats.histogram.value_at_quantile(0.99)?),
+        result.status_codes,
+    );
+
+    let schedule = RequestSchedule::new(1_000, 1_000., DistributionType::Uniform)?;
+    config.duration = Duration::from_secs(1);
+    config.warmup = Duration::from_secs(0);
+    config.cooldown = Duration::from_secs(0);
+    let result = run_http_load(
+        &config,
+        schedule,
+        &RequestTemplate::get("/"),
+        fixed_body(vec![]),
+    )
+    .await?;
+    println!("GET: {:?}", result.status_codes);
+
+    Ok(())
+}
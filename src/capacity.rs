@@ -0,0 +1,275 @@
+//! Estimates the maximum sustainable throughput from the results of a rate sweep.
+use super::error::{Error, Result};
+use super::sweep::SweepPoint;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapacityConfig {
+    // a point is saturated once achieved throughput falls below this fraction of offered load
+    pub min_achieved_fraction: f64,
+    // a point is saturated once latency at `quantile` exceeds this multiple of its value at the
+    // lowest offered load
+    pub latency_factor: f64,
+    pub quantile: f64,
+    // a point is saturated once latency at `quantile` exceeds this
+    pub slo: Option<Duration>,
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        CapacityConfig {
+            min_achieved_fraction: 0.95,
+            latency_factor: 3.0,
+            quantile: 0.99,
+            slo: None,
+        }
+    }
+}
+
+impl CapacityConfig {
+    /// Saturation is defined by the SLO alone, plus throughput keeping up with offered load.
+    pub fn at_slo(quantile: f64, slo: Duration) -> Self {
+        CapacityConfig {
+            latency_factor: f64::INFINITY,
+            quantile,
+            slo: Some(slo),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SaturationCriterion {
+    // achieved throughput diverged from offered load
+    ThroughputDivergence,
+    // latency grew past the configured multiple of the low-load baseline
+    LatencyInflation,
+    // latency exceeded the SLO
+    SloViolation,
+}
+
+/// How one sweep point measured against the saturation criteria.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PointEvidence {
+    pub offered_rate: f64,
+    pub achieved_rate: f64,
+    // achieved_rate / offered_rate
+    pub achieved_fraction: f64,
+    // latency at the configured quantile, in nanoseconds; None if no request succeeded
+    pub latency: Option<u64>,
+    // latency / baseline latency; None unless both are known
+    pub latency_ratio: Option<f64>,
+    // criteria this point violates; empty if it is sustainable
+    pub violations: Vec<SaturationCriterion>,
+}
+
+impl PointEvidence {
+    pub fn is_sustainable(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CapacityEstimate {
+    // highest offered rate at which this and every lower point are sustainable;
+    // None if even the lowest point is saturated
+    pub max_sustainable_rate: Option<f64>,
+    // achieved throughput at that rate
+    pub max_sustainable_throughput: Option<f64>,
+    // lowest offered rate found to be saturated; None if no point is
+    pub first_saturated_rate: Option<f64>,
+    // criteria violated at the first saturated rate
+    pub limiting_criteria: Vec<SaturationCriterion>,
+    // latency at the configured quantile at the lowest offered rate, in nanoseconds
+    pub baseline_latency: Option<u64>,
+    pub evidence: Vec<PointEvidence>,
+}
+
+/// Estimates the maximum sustainable throughput, with the evidence for each point.
+pub fn estimate_capacity(
+    points: &[SweepPoint],
+    config: &CapacityConfig,
+) -> Result<CapacityEstimate> {
+    if points.is_empty() {
+        return Err(Error::InvalidArgument(
+            "Cannot estimate capacity from an empty sweep".to_string(),
+        ));
+    }
+
+    if !(0.0..=1.0).contains(&config.quantile) {
+        return Err(Error::InvalidArgument(format!(
+            "Quantile must be in [0, 1]: {}",
+            config.quantile
+        )));
+    }
+
+    let mut points: Vec<&SweepPoint> = points.iter().collect();
+    points.sort_by(|a, b| a.offered_rate.total_cmp(&b.offered_rate));
+
+    // an empty histogram means no request succeeded
+    let latency_of = |p: &SweepPoint| p.stats.histogram.value_at_quantile(config.quantile).ok();
+    let baseline_latency = latency_of(points[0]);
+    let mut evidence = Vec::with_capacity(points.len());
+    for p in points {
+        let latency = latency_of(p);
+        let achieved_fraction = if p.offered_rate > 0.0 {
+            p.achieved_rate / p.offered_rate
+        } else {
+            0.0
+        };
+        let latency_ratio = match (latency, baseline_latency) {
+            (Some(l), Some(b)) => Some(l as f64 / b.max(1) as f64),
+            _ => None,
+        };
+
+        // without a latency, the point counts as over every latency limit
+        let mut violations = vec![];
+        if achieved_fraction < config.min_achieved_fraction {
+            violations.push(SaturationCriterion::ThroughputDivergence);
+        }
+        if latency.is_none() && config.latency_factor.is_finite()
+            || latency_ratio.is_some_and(|r| r > config.latency_factor)
+        {
+            violations.push(SaturationCriterion::LatencyInflation);
+        }
+        if let Some(slo) = config.slo {
+            if latency.is_none_or(|l| Duration::from_nanos(l) > slo) {
+                violations.push(SaturationCriterion::SloViolation);
+            }
+        }
+
+        evidence.push(PointEvidence {
+            offered_rate: p.offered_rate,
+            achieved_rate: p.achieved_rate,
+            achieved_fraction,
+            latency,
+            latency_ratio,
+            violations,
+        });
+    }
+
+    let first_saturated = evidence.iter().position(|e| !e.is_sustainable());
+    let last_sustainable = match first_saturated {
+        Some(0) => None,
+        Some(i) => Some(&evidence[i - 1]),
+        None => evidence.last(),
+    };
+
+    Ok(CapacityEstimate {
+        max_sustainable_rate: last_sustainable.map(|e| e.offered_rate),
+        max_sustainable_throughput: last_sustainable.map(|e| e.achieved_rate),
+        first_saturated_rate: first_saturated.map(|i| evidence[i].offered_rate),
+        limiting_criteria: first_saturated
+            .map(|i| evidence[i].violations.clone())
+            .unwrap_or_default(),
+        baseline_latency,
+        evidence,
+    })
+}
+
+/// The highest sustainable throughput at which latency at `quantile` stays within `slo`.
+pub fn capacity_at_slo(
+    points: &[SweepPoint],
+    quantile: f64,
+    slo: Duration,
+) -> Result<CapacityEstimate> {
+    estimate_capacity(points, &CapacityConfig::at_slo(quantile, slo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary_stats::test_stats;
+
+    // a one-second point at `offered` requests per second, every response taking `latency_ns`
+    fn point(offered: f64, achieved: f64, latency_ns: Option<u64>) -> SweepPoint {
+        let latencies = match latency_ns {
+            Some(l) => vec![l; achieved as usize],
+            None => vec![],
+        };
+        SweepPoint {
+            offered_rate: offered,
+            achieved_rate: achieved,
+            stats: test_stats(offered as usize, &latencies),
+        }
+    }
+
+    #[test]
+    fn stops_at_throughput_divergence() {
+        let points = [
+            point(400.0, 300.0, Some(150)),
+            point(100.0, 100.0, Some(100)),
+            point(200.0, 199.0, Some(120)),
+        ];
+        let est = estimate_capacity(&points, &CapacityConfig::default()).unwrap();
+        assert_eq!(est.baseline_latency, Some(100));
+        assert_eq!(est.max_sustainable_rate, Some(200.0));
+        assert_eq!(est.max_sustainable_throughput, Some(199.0));
+        assert_eq!(est.first_saturated_rate, Some(400.0));
+        assert_eq!(
+            est.limiting_criteria,
+            [SaturationCriterion::ThroughputDivergence]
+        );
+        let offered: Vec<_> = est.evidence.iter().map(|e| e.offered_rate).collect();
+        assert_eq!(offered, [100.0, 200.0, 400.0]);
+    }
+
+    #[test]
+    fn stops_at_latency_inflation_or_slo() {
+        let points = [
+            point(100.0, 100.0, Some(100)),
+            point(200.0, 200.0, Some(250)),
+            point(300.0, 300.0, Some(400)),
+        ];
+        let est = estimate_capacity(&points, &CapacityConfig::default()).unwrap();
+        assert_eq!(est.max_sustainable_rate, Some(200.0));
+        assert_eq!(
+            est.limiting_criteria,
+            [SaturationCriterion::LatencyInflation]
+        );
+        assert_eq!(est.evidence[2].latency_ratio, Some(4.0));
+
+        let est = capacity_at_slo(&points, 0.99, Duration::from_nanos(200)).unwrap();
+        assert_eq!(est.max_sustainable_rate, Some(100.0));
+        assert_eq!(est.limiting_criteria, [SaturationCriterion::SloViolation]);
+    }
+
+    #[test]
+    fn point_without_responses_is_over_slo() {
+        let points = [point(100.0, 100.0, Some(100)), point(200.0, 0.0, None)];
+        let est = capacity_at_slo(&points, 0.99, Duration::from_secs(1)).unwrap();
+        assert_eq!(est.max_sustainable_rate, Some(100.0));
+        assert_eq!(est.evidence[1].latency, None);
+        assert_eq!(
+            est.limiting_criteria,
+            [
+                SaturationCriterion::ThroughputDivergence,
+                SaturationCriterion::SloViolation
+            ]
+        );
+
+        // nothing succeeded even at the lowest rate
+        let est = estimate_capacity(&points[1..], &CapacityConfig::default()).unwrap();
+        assert_eq!(est.baseline_latency, None);
+        assert_eq!(est.max_sustainable_rate, None);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(estimate_capacity(&[], &CapacityConfig::default()).is_err());
+        let config = CapacityConfig {
+            quantile: 99.0,
+            ..Default::default()
+        };
+        assert!(estimate_capacity(&[point(100.0, 100.0, Some(100))], &config).is_err());
+        // NaN rates sort last instead of panicking
+        let points = [point(f64::NAN, 0.0, None), point(100.0, 100.0, Some(100))];
+        let est = estimate_capacity(&points, &CapacityConfig::default()).unwrap();
+        assert_eq!(est.max_sustainable_rate, Some(100.0));
+    }
+}
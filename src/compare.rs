@@ -0,0 +1,401 @@
+//! Statistical comparison of two runs.
+//!
+//! Compares a baseline and a candidate latency distribution, reporting the difference at each
+//! quantile with a bootstrap confidence interval, and a two-sample test of whether the
+//! distributions differ at all. The verdict is decided by the quantile differences, since a
+//! regression in the tail may not move the distribution test.
+use super::error::{Error, Result};
+use super::stats::{bucket_quantile, kolmogorov_survival, normal_cdf, sorted_quantile};
+use super::summary_stats::{SummaryHistogram, SummaryStats};
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Binomial, Distribution};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DistributionTest {
+    KolmogorovSmirnov,
+    MannWhitney,
+}
+
+impl std::str::FromStr for DistributionTest {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<DistributionTest> {
+        Ok(match s {
+            "ks" | "KS" | "kolmogorov-smirnov" => DistributionTest::KolmogorovSmirnov,
+            "mw" | "MW" | "mann-whitney" => DistributionTest::MannWhitney,
+            x => {
+                return Err(Error::UnknownName {
+                    kind: "distribution test",
+                    name: x.to_string(),
+                })
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompareConfig {
+    pub quantiles: Vec<f64>,
+    // significance level; confidence intervals are at 1 - alpha
+    pub alpha: f64,
+    pub bootstrap_iterations: usize,
+    pub test: DistributionTest,
+    // seed for bootstrap resampling, so comparisons are reproducible
+    pub seed: u64,
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        CompareConfig {
+            quantiles: vec![0.5, 0.9, 0.99, 0.999],
+            alpha: 0.05,
+            bootstrap_iterations: 1000,
+            test: DistributionTest::KolmogorovSmirnov,
+            seed: 0,
+        }
+    }
+}
+
+/// Difference between candidate and baseline at one quantile, in nanoseconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuantileDiff {
+    pub quantile: f64,
+    pub baseline: u64,
+    pub candidate: u64,
+    // candidate - baseline
+    pub difference: i64,
+    // bootstrap confidence interval of the difference
+    pub ci_low: f64,
+    pub ci_high: f64,
+    // whether the confidence interval excludes zero
+    pub significant: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TestResult {
+    pub test: DistributionTest,
+    pub statistic: f64,
+    pub p_value: f64,
+    pub significant: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Verdict {
+    // no compared quantile differs significantly
+    Indistinguishable,
+    // the candidate is significantly slower at some compared quantile
+    Regressed,
+    // the candidate is significantly faster at some compared quantile, and slower at none
+    Improved,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Comparison {
+    pub alpha: f64,
+    pub quantiles: Vec<QuantileDiff>,
+    pub test: TestResult,
+    pub verdict: Verdict,
+}
+
+impl Comparison {
+    pub fn quantile(&self, quantile: f64) -> Option<&QuantileDiff> {
+        self.quantiles
+            .iter()
+            .find(|q| (q.quantile - quantile).abs() < f64::EPSILON)
+    }
+}
+
+/// Compares the latency histograms of two runs.
+pub fn compare_stats(
+    baseline: &SummaryStats,
+    candidate: &SummaryStats,
+    config: &CompareConfig,
+) -> Result<Comparison> {
+    compare_histograms(&baseline.histogram, &candidate.histogram, config)
+}
+
+pub fn compare_histograms(
+    baseline: &SummaryHistogram,
+    candidate: &SummaryHistogram,
+    config: &CompareConfig,
+) -> Result<Comparison> {
+    if baseline.count == 0 || candidate.count == 0 {
+        return Err(Error::EmptyHistogram);
+    }
+    // bucket boundaries differ, so quantiles aren't comparable
+    if baseline.precision != candidate.precision {
+        return Err(Error::PrecisionMismatch(
+            baseline.precision,
+            candidate.precision,
+        ));
+    }
+    if !(config.alpha > 0.0 && config.alpha < 1.0) {
+        return Err(Error::InvalidArgument(format!(
+            "Significance level must be in (0, 1): {}",
+            config.alpha
+        )));
+    }
+    if let Some(q) = config.quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+        return Err(Error::InvalidArgument(format!(
+            "Quantiles must be in [0, 1]: {}",
+            q
+        )));
+    }
+
+    let quantiles = quantile_diffs(baseline, candidate, config)?;
+    let test = match config.test {
+        DistributionTest::KolmogorovSmirnov => kolmogorov_smirnov(baseline, candidate),
+        DistributionTest::MannWhitney => mann_whitney(baseline, candidate),
+    };
+    let test = TestResult {
+        significant: test.1 < config.alpha,
+        test: config.test,
+        statistic: test.0,
+        p_value: test.1,
+    };
+
+    let verdict = if quantiles.iter().any(|q| q.significant && q.ci_low > 0.0) {
+        Verdict::Regressed
+    } else if quantiles.iter().any(|q| q.significant && q.ci_high < 0.0) {
+        Verdict::Improved
+    } else {
+        Verdict::Indistinguishable
+    };
+
+    Ok(Comparison {
+        alpha: config.alpha,
+        quantiles,
+        test,
+        verdict,
+    })
+}
+
+fn quantile_diffs(
+    baseline: &SummaryHistogram,
+    candidate: &SummaryHistogram,
+    config: &CompareConfig,
+) -> Result<Vec<QuantileDiff>> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let (b_vals, b_counts): (Vec<u64>, Vec<u64>) = baseline.map.iter().unzip();
+    let (c_vals, c_counts): (Vec<u64>, Vec<u64>) = candidate.map.iter().unzip();
+
+    // bootstrap distribution of the difference at each quantile
+    let mut diffs = vec![Vec::with_capacity(config.bootstrap_iterations); config.quantiles.len()];
+    for _ in 0..config.bootstrap_iterations {
+        let b_sample = resample(&b_counts, &mut rng)?;
+        let c_sample = resample(&c_counts, &mut rng)?;
+        for (i, q) in config.quantiles.iter().enumerate() {
+            let b = bucket_quantile(&b_vals, &b_sample, *q).unwrap_or(0);
+            let c = bucket_quantile(&c_vals, &c_sample, *q).unwrap_or(0);
+            diffs[i].push(c as f64 - b as f64);
+        }
+    }
+
+    config
+        .quantiles
+        .iter()
+        .zip(diffs)
+        .map(|(q, mut d)| {
+            d.sort_by(f64::total_cmp);
+            let b = baseline.value_at_quantile(*q)?;
+            let c = candidate.value_at_quantile(*q)?;
+            let ci_low = sorted_quantile(&d, config.alpha / 2.0);
+            let ci_high = sorted_quantile(&d, 1.0 - config.alpha / 2.0);
+            Ok(QuantileDiff {
+                quantile: *q,
+                baseline: b,
+                candidate: c,
+                difference: c as i64 - b as i64,
+                ci_low,
+                ci_high,
+                significant: !d.is_empty() && (ci_low > 0.0 || ci_high < 0.0),
+            })
+        })
+        .collect()
+}
+
+// Draws a bootstrap resample of the same size as the histogram, returning new bucket counts.
+// Multinomial sampling is done as a sequence of binomials, so the cost depends on the number of
+// buckets rather than the number of samples.
+fn resample(counts: &[u64], rng: &mut StdRng) -> Result<Vec<u64>> {
+    let total: u64 = counts.iter().sum();
+    let mut remaining_n = total;
+    let mut remaining_p = total;
+    let mut sample = Vec::with_capacity(counts.len());
+    for c in counts {
+        if remaining_n == 0 || remaining_p == 0 {
+            sample.push(0);
+            continue;
+        }
+        let p = (*c as f64 / remaining_p as f64).min(1.0);
+        let drawn = match Binomial::new(remaining_n, p) {
+            Ok(b) => b.sample(rng),
+            Err(e) => {
+                return Err(Error::InvalidArgument(format!(
+                    "Failed to initialize binomial distribution: {:?}",
+                    e
+                )))
+            }
+        };
+        sample.push(drawn);
+        remaining_n -= drawn;
+        remaining_p -= c;
+    }
+    Ok(sample)
+}
+
+// Returns (D statistic, p-value) of the two-sample Kolmogorov-Smirnov test.
+fn kolmogorov_smirnov(a: &SummaryHistogram, b: &SummaryHistogram) -> (f64, f64) {
+    let keys: BTreeSet<u64> = a.map.keys().chain(b.map.keys()).copied().collect();
+    let (n_a, n_b) = (a.count as f64, b.count as f64);
+    let (mut cum_a, mut cum_b) = (0u64, 0u64);
+    let mut d: f64 = 0.0;
+    for k in keys {
+        cum_a += a.map.get(&k).copied().unwrap_or(0);
+        cum_b += b.map.get(&k).copied().unwrap_or(0);
+        d = d.max((cum_a as f64 / n_a - cum_b as f64 / n_b).abs());
+    }
+
+    let en = (n_a * n_b / (n_a + n_b)).sqrt();
+    let p = kolmogorov_survival((en + 0.12 + 0.11 / en) * d);
+    (d, p)
+}
+
+// Returns (U statistic of a, two-sided p-value) of the Mann-Whitney U test, using the normal
+// approximation with a tie correction.
+fn mann_whitney(a: &SummaryHistogram, b: &SummaryHistogram) -> (f64, f64) {
+    let keys: BTreeSet<u64> = a.map.keys().chain(b.map.keys()).copied().collect();
+    let (n_a, n_b) = (a.count as f64, b.count as f64);
+    let n = n_a + n_b;
+    let mut rank_sum_a = 0.0;
+    let mut tie_sum = 0.0;
+    let mut seen = 0.0;
+    for k in keys {
+        let c_a = a.map.get(&k).copied().unwrap_or(0) as f64;
+        let c_b = b.map.get(&k).copied().unwrap_or(0) as f64;
+        let t = c_a + c_b;
+        // all tied values get the average of the ranks they span
+        let avg_rank = seen + (t + 1.0) / 2.0;
+        rank_sum_a += c_a * avg_rank;
+        tie_sum += t * t * t - t;
+        seen += t;
+    }
+
+    let u = rank_sum_a - n_a * (n_a + 1.0) / 2.0;
+    let mean_u = n_a * n_b / 2.0;
+    let var_u = n_a * n_b / 12.0 * ((n + 1.0) - tie_sum / (n * (n - 1.0)));
+    if var_u <= 0.0 {
+        return (u, 1.0);
+    }
+    let z = (u - mean_u) / var_u.sqrt();
+    let p = 2.0 * (1.0 - normal_cdf(z.abs()));
+    (u, p.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hist(buckets: &[(u64, u64)]) -> SummaryHistogram {
+        let mut h = SummaryHistogram::default();
+        for (latency, count) in buckets {
+            for _ in 0..*count {
+                h.record(*latency);
+            }
+        }
+        h
+    }
+
+    fn compare(baseline: &SummaryHistogram, candidate: &SummaryHistogram) -> Comparison {
+        compare_histograms(baseline, candidate, &CompareConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn identical_runs_are_indistinguishable() {
+        let h = hist(&[(100, 500), (200, 400), (1000, 100)]);
+        let c = compare(&h, &h);
+        assert_eq!(c.verdict, Verdict::Indistinguishable);
+        assert!(!c.test.significant);
+        assert!(c
+            .quantiles
+            .iter()
+            .all(|q| q.difference == 0 && !q.significant));
+    }
+
+    #[test]
+    fn tail_regression_is_reported() {
+        // same median, but 5% of requests are much slower
+        let baseline = hist(&[(100, 1000)]);
+        let candidate = hist(&[(100, 950), (10_000, 50)]);
+        let c = compare(&baseline, &candidate);
+        assert_eq!(c.verdict, Verdict::Regressed);
+        assert_eq!(c.quantile(0.5).unwrap().difference, 0);
+        assert!(c.quantile(0.99).unwrap().significant);
+    }
+
+    #[test]
+    fn faster_median_with_slower_tail_is_a_regression() {
+        let baseline = hist(&[(200, 1000)]);
+        let candidate = hist(&[(100, 950), (10_000, 50)]);
+        let c = compare(&baseline, &candidate);
+        assert!(c.test.significant);
+        assert!(c.quantile(0.5).unwrap().difference < 0);
+        assert_eq!(c.verdict, Verdict::Regressed);
+    }
+
+    #[test]
+    fn uniform_speedup_is_an_improvement() {
+        let baseline = hist(&[(200, 500), (400, 500)]);
+        let candidate = hist(&[(100, 500), (200, 500)]);
+        let c = compare(&baseline, &candidate);
+        assert_eq!(c.verdict, Verdict::Improved);
+        assert!(c.quantiles.iter().all(|q| q.difference < 0));
+    }
+
+    #[test]
+    fn mann_whitney_detects_a_shift() {
+        let baseline = hist(&[(100, 500), (200, 500)]);
+        let candidate = hist(&[(200, 500), (300, 500)]);
+        let config = CompareConfig {
+            test: "mw".parse().unwrap(),
+            ..Default::default()
+        };
+        let c = compare_histograms(&baseline, &candidate, &config).unwrap();
+        assert_eq!(c.test.test, DistributionTest::MannWhitney);
+        assert!(c.test.p_value < 1e-6);
+        assert_eq!(c.verdict, Verdict::Regressed);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let h = hist(&[(100, 10)]);
+        let config = CompareConfig::default();
+        assert!(matches!(
+            compare_histograms(&SummaryHistogram::default(), &h, &config),
+            Err(Error::EmptyHistogram)
+        ));
+
+        let coarse = SummaryHistogram {
+            precision: Some(1000),
+            ..hist(&[(100, 10)])
+        };
+        assert!(matches!(
+            compare_histograms(&h, &coarse, &config),
+            Err(Error::PrecisionMismatch(None, Some(1000)))
+        ));
+
+        let bad_alpha = CompareConfig {
+            alpha: 1.5,
+            ..Default::default()
+        };
+        assert!(compare_histograms(&h, &h, &bad_alpha).is_err());
+        assert!("t-test".parse::<DistributionTest>().is_err());
+    }
+}
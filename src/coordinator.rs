@@ -0,0 +1,534 @@
+//! Running one experiment across several client processes.
+//!
+//! A [`Coordinator`] waits for a fixed number of clients to register over TCP, then gives each
+//! an [`Assignment`]: its share of the total request rate, its own schedule seed, and a shared
+//! start time. Clients wait for the start, run their load, and upload their [`SummaryStats`] and,
+//! optionally, their timestamps. The coordinator merges the uploads into one global summary.
+//!
+//! Messages are JSON, one per line. The start time is wall-clock time, so clients on different
+//! machines need synchronized clocks.
+use super::error::{Error, Result};
+use super::histogram::LatencyMap;
+use super::requests::{DistributionType, RequestSchedule};
+use super::server::{accept_loop, AbortOnDrop};
+use super::summary_stats::SummaryStats;
+use super::timestamps::{RunAnchor, TimestampFormat, TimestampLog};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone)]
+pub struct CoordinatorConfig {
+    // the experiment starts once this many clients have registered
+    pub clients: usize,
+    // requests per second, summed over all clients
+    pub total_rate: f64,
+    pub distribution: DistributionType,
+    pub duration: Duration,
+    // time between the last registration and the start, to get assignments to every client
+    pub start_delay: Duration,
+    // client i generates its schedule with seed + i
+    pub seed: u64,
+    // how long to wait for registrations, and for results after the run ends
+    pub timeout: Duration,
+    // how long a new connection has to register before it is dropped
+    pub register_timeout: Duration,
+}
+
+impl Default for CoordinatorConfig {
+    fn default() -> Self {
+        CoordinatorConfig {
+            clients: 1,
+            total_rate: 1000.0,
+            distribution: DistributionType::Exponential,
+            duration: Duration::from_secs(10),
+            start_delay: Duration::from_secs(1),
+            seed: 0,
+            timeout: Duration::from_secs(60),
+            register_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What one client should run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub client_index: usize,
+    pub clients: usize,
+    pub seed: u64,
+    // this client's requests per second
+    pub rate: f64,
+    pub distribution: DistributionType,
+    pub num_requests: usize,
+    pub duration: Duration,
+    // wall-clock start time, in nanoseconds since the unix epoch
+    pub start_unix_nanos: u64,
+}
+
+impl Assignment {
+    pub fn schedule(&self) -> Result<RequestSchedule> {
+        RequestSchedule::new_seeded(self.num_requests, self.rate, self.distribution, self.seed)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+// externally tagged, since internal tags can't round-trip the integer map keys in SummaryStats
+#[serde(rename_all = "snake_case")]
+enum Message {
+    Register {
+        name: String,
+    },
+    Assign(Assignment),
+    Results {
+        stats: SummaryStats,
+        // a timestamp log in CSV format
+        timestamps: Option<String>,
+    },
+}
+
+struct Connection(BufReader<TcpStream>);
+
+impl Connection {
+    async fn send(&mut self, msg: &Message) -> Result<()> {
+        let mut line = serde_json::to_vec(msg)?;
+        line.push(b'\n');
+        self.0.get_mut().write_all(&line).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Message> {
+        let mut line = String::new();
+        if self.0.read_line(&mut line).await? == 0 {
+            return Err(Error::Protocol("connection closed".to_string()));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+fn unix_nanos_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// One client's uploaded results.
+#[derive(Debug, Clone)]
+pub struct ClientReport {
+    pub name: String,
+    pub stats: SummaryStats,
+    pub timestamps: Option<TimestampLog>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExperimentResult {
+    // merged over every client
+    pub stats: SummaryStats,
+    // in registration order
+    pub clients: Vec<ClientReport>,
+    pub start_unix_nanos: u64,
+}
+
+impl ExperimentResult {
+    /// Each client's timestamps as a [`LatencyMap`] on a timeline shared with the others.
+    pub fn aligned_latencies(&self) -> Result<Vec<Option<LatencyMap>>> {
+        let reference = RunAnchor::now();
+        self.clients
+            .iter()
+            .map(|c| {
+                c.timestamps
+                    .as_ref()
+                    .map(|t| t.to_latency_map_aligned(&reference))
+                    .transpose()
+            })
+            .collect()
+    }
+}
+
+pub struct Coordinator {
+    listener: TcpListener,
+    config: CoordinatorConfig,
+}
+
+impl Coordinator {
+    /// Binds to `addr`; use port 0 to pick a free port.
+    pub async fn bind(addr: SocketAddr, config: CoordinatorConfig) -> Result<Self> {
+        if config.clients == 0 {
+            return Err(Error::InvalidArgument(
+                "Experiment needs at least one client".to_string(),
+            ));
+        }
+        if !config.total_rate.is_finite() || config.total_rate <= 0.0 {
+            return Err(Error::InvalidArgument(format!(
+                "Rate must be positive: {}",
+                config.total_rate
+            )));
+        }
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|source| Error::Bind {
+                addr: addr.to_string(),
+                source,
+            })?;
+        Ok(Coordinator { listener, config })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Runs one experiment: registration, assignment, and result collection.
+    pub async fn run(self) -> Result<ExperimentResult> {
+        let config = &self.config;
+        let (registered, mut registrations) = mpsc::unbounded_channel();
+        let accept = AbortOnDrop(tokio::spawn(accept_registrations(
+            self.listener,
+            config.register_timeout,
+            registered,
+        )));
+        let mut clients = Vec::with_capacity(config.clients);
+        tokio::time::timeout(config.timeout, async {
+            while clients.len() < config.clients {
+                match registrations.recv().await {
+                    Some(client) => clients.push(client),
+                    None => {
+                        return Err(Error::TaskFailed(
+                            "registration listener exited".to_string(),
+                        ))
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|_| {
+            Error::Timeout(format!(
+                "only {} of {} clients registered",
+                clients.len(),
+                config.clients
+            ))
+        })??;
+        // later connections are dropped
+        drop(accept);
+
+        let rate = config.total_rate / config.clients as f64;
+        let num_requests = (rate * config.duration.as_secs_f64()).ceil() as usize;
+        let start_unix_nanos = unix_nanos_now() + config.start_delay.as_nanos() as u64;
+        for (i, (name, conn)) in clients.iter_mut().enumerate() {
+            let assignment = Assignment {
+                client_index: i,
+                clients: config.clients,
+                seed: config.seed.wrapping_add(i as u64),
+                rate,
+                distribution: config.distribution,
+                num_requests,
+                duration: config.duration,
+                start_unix_nanos,
+            };
+            conn.send(&Message::Assign(assignment))
+                .await
+                .map_err(|e| Error::Client {
+                    name: name.clone(),
+                    source: Box::new(e),
+                })?;
+        }
+
+        let deadline = config.start_delay + config.duration + config.timeout;
+        let reports = tokio::time::timeout(deadline, async {
+            let mut reports = Vec::with_capacity(clients.len());
+            for (name, conn) in clients.iter_mut() {
+                let client_error = |e| Error::Client {
+                    name: name.clone(),
+                    source: Box::new(e),
+                };
+                let (stats, timestamps) = match conn.recv().await.map_err(client_error)? {
+                    Message::Results { stats, timestamps } => (stats, timestamps),
+                    m => {
+                        return Err(client_error(Error::Protocol(format!(
+                            "expected results, got {:?}",
+                            m
+                        ))))
+                    }
+                };
+                let timestamps = timestamps
+                    .map(|t| TimestampLog::read(&mut t.as_bytes()))
+                    .transpose()
+                    .map_err(client_error)?;
+                reports.push(ClientReport {
+                    name: name.clone(),
+                    stats,
+                    timestamps,
+                });
+            }
+            Ok(reports)
+        })
+        .await
+        .map_err(|_| Error::Timeout("waiting for client results".to_string()))??;
+
+        let stats =
+            SummaryStats::merge(&reports.iter().map(|r| r.stats.clone()).collect::<Vec<_>>())?;
+        Ok(ExperimentResult {
+            stats,
+            clients: reports,
+            start_unix_nanos,
+        })
+    }
+}
+
+/// Accepts connections and reads each one's registration on its own task, so a slow or silent
+/// connection doesn't hold up the others.
+async fn accept_registrations(
+    listener: TcpListener,
+    register_timeout: Duration,
+    registered: mpsc::UnboundedSender<(String, Connection)>,
+) {
+    accept_loop(listener, move |stream, from| {
+        let registered = registered.clone();
+        async move {
+            let _ = &from;
+            let mut conn = Connection(BufReader::new(stream));
+            match tokio::time::timeout(register_timeout, conn.recv()).await {
+                Ok(Ok(Message::Register { name })) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(?name, ?from, "Client registered");
+                    // the coordinator stops listening once every client has registered
+                    let _ = registered.send((name, conn));
+                }
+                Ok(Ok(m)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(?from, ?m, "Expected registration");
+                    let _ = &m;
+                }
+                Ok(Err(e)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(?from, err = %e, "Registration failed");
+                    let _ = &e;
+                }
+                Err(_) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(?from, "Timed out waiting for registration");
+                }
+            }
+        }
+    })
+    .await
+}
+
+/// A client's connection to a [`Coordinator`].
+pub struct CoordinatedClient {
+    conn: Connection,
+    assignment: Assignment,
+}
+
+impl CoordinatedClient {
+    /// Registers with the coordinator and waits for every other client to register.
+    pub async fn join(coordinator: SocketAddr, name: &str) -> Result<Self> {
+        let stream = TcpStream::connect(coordinator)
+            .await
+            .map_err(|source| Error::Connect {
+                addr: coordinator.to_string(),
+                source,
+            })?;
+        let mut conn = Connection(BufReader::new(stream));
+        conn.send(&Message::Register {
+            name: name.to_string(),
+        })
+        .await?;
+        match conn.recv().await? {
+            Message::Assign(assignment) => Ok(CoordinatedClient { conn, assignment }),
+            m => Err(Error::Protocol(format!(
+                "expected an assignment, got {:?}",
+                m
+            ))),
+        }
+    }
+
+    pub fn assignment(&self) -> &Assignment {
+        &self.assignment
+    }
+
+    /// Waits until the shared start time and returns an anchor for the run.
+    ///
+    /// Create tickers and recorders after this returns, so their start times line up with the
+    /// other clients'.
+    pub async fn wait_for_start(&self) -> RunAnchor {
+        let now = RunAnchor::now();
+        if let Some(wait) = self.assignment.start_unix_nanos.checked_sub(now.unix_nanos) {
+            let start = now.instant + Duration::from_nanos(wait);
+            // sleep most of the way, then spin for precision
+            if let Some(coarse) = wait.checked_sub(2_000_000) {
+                tokio::time::sleep(Duration::from_nanos(coarse)).await;
+            }
+            while Instant::now() < start {
+                tokio::task::yield_now().await;
+            }
+        } else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                late_ns = now.unix_nanos - self.assignment.start_unix_nanos,
+                "Starting late"
+            );
+        }
+        RunAnchor::now()
+    }
+
+    /// Sends this client's results to the coordinator, with its timestamps if given.
+    pub async fn upload(
+        mut self,
+        stats: &SummaryStats,
+        latencies: Option<&LatencyMap>,
+    ) -> Result<()> {
+        let timestamps = match latencies {
+            Some(map) => {
+                let mut csv = Vec::new();
+                TimestampLog::from_latency_map(map)?.write(&mut csv, TimestampFormat::Csv)?;
+                // the CSV format is plain ASCII
+                Some(String::from_utf8_lossy(&csv).into_owned())
+            }
+            None => None,
+        };
+        self.conn
+            .send(&Message::Results {
+                stats: stats.clone(),
+                timestamps,
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary_stats::test_stats;
+
+    fn config(clients: usize) -> CoordinatorConfig {
+        CoordinatorConfig {
+            clients,
+            total_rate: 1000.0,
+            duration: Duration::from_millis(100),
+            start_delay: Duration::from_millis(20),
+            timeout: Duration::from_secs(5),
+            register_timeout: Duration::from_millis(200),
+            ..Default::default()
+        }
+    }
+
+    async fn coordinator(config: CoordinatorConfig) -> (SocketAddr, Coordinator) {
+        let coordinator = Coordinator::bind("127.0.0.1:0".parse().unwrap(), config)
+            .await
+            .unwrap();
+        (coordinator.local_addr().unwrap(), coordinator)
+    }
+
+    fn stats(sent: usize, latency_ns: u64) -> SummaryStats {
+        test_stats(sent, &vec![latency_ns; sent])
+    }
+
+    async fn client(addr: SocketAddr, name: &str) -> Result<Assignment> {
+        let client = CoordinatedClient::join(addr, name).await?;
+        let assignment = client.assignment().clone();
+        client.wait_for_start().await;
+        client
+            .upload(&stats(assignment.num_requests, 1000), None)
+            .await?;
+        Ok(assignment)
+    }
+
+    #[tokio::test]
+    async fn assigns_and_merges() {
+        let (addr, coordinator) = coordinator(config(2)).await;
+        let run = tokio::spawn(coordinator.run());
+        let a = tokio::spawn(client(addr, "a"));
+        let b = tokio::spawn(client(addr, "b"));
+        let (a, b) = (a.await.unwrap().unwrap(), b.await.unwrap().unwrap());
+        let result = run.await.unwrap().unwrap();
+
+        let mut indices = [a.client_index, b.client_index];
+        indices.sort();
+        assert_eq!(indices, [0, 1]);
+        assert_ne!(a.seed, b.seed);
+        assert_eq!(a.rate, 500.0);
+        assert_eq!(a.num_requests, 50);
+        assert_eq!(a.start_unix_nanos, b.start_unix_nanos);
+        assert_eq!(result.start_unix_nanos, a.start_unix_nanos);
+
+        assert_eq!(result.clients.len(), 2);
+        assert_eq!(result.stats.total_objects_sent, 100);
+        assert_eq!(result.stats.histogram.count, 100);
+        assert_eq!(a.schedule().unwrap().len(), 50);
+    }
+
+    #[tokio::test]
+    async fn silent_connections_do_not_block_registration() {
+        let config = CoordinatorConfig {
+            register_timeout: Duration::from_secs(10),
+            ..config(1)
+        };
+        let (addr, coordinator) = coordinator(config).await;
+        let run = tokio::spawn(coordinator.run());
+        // connects first but never registers
+        let _silent = TcpStream::connect(addr).await.unwrap();
+        // sends something other than a registration
+        let mut wrong = TcpStream::connect(addr).await.unwrap();
+        wrong.write_all(b"{\"register\":{}}\n").await.unwrap();
+
+        let started = Instant::now();
+        client(addr, "a").await.unwrap();
+        let result = run.await.unwrap().unwrap();
+        assert_eq!(result.clients[0].name, "a");
+        // registration didn't wait out the silent connection's timeout
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn times_out_without_enough_clients() {
+        let config = CoordinatorConfig {
+            timeout: Duration::from_millis(100),
+            ..config(2)
+        };
+        let (addr, coordinator) = coordinator(config).await;
+        let run = tokio::spawn(coordinator.run());
+        let _client = tokio::spawn(CoordinatedClient::join(addr, "a"));
+        match run.await.unwrap() {
+            Err(Error::Timeout(msg)) => assert!(msg.contains("1 of 2"), "{}", msg),
+            other => panic!(
+                "expected a timeout, got {:?}",
+                other.map(|r| r.clients.len())
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_configs() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        assert!(Coordinator::bind(addr, config(0)).await.is_err());
+        let config = CoordinatorConfig {
+            total_rate: f64::NAN,
+            ..config(1)
+        };
+        assert!(matches!(
+            Coordinator::bind(addr, config).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let msg = Message::Register {
+            name: "a".to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, "{\"register\":{\"name\":\"a\"}}");
+        let results = Message::Results {
+            stats: stats(3, 1000),
+            timestamps: None,
+        };
+        match serde_json::from_str(&serde_json::to_string(&results).unwrap()).unwrap() {
+            Message::Results { stats, .. } => assert_eq!(stats.total_objects_sent, 3),
+            m => panic!("unexpected message {:?}", m),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Errors returned by the library.
+//!
+//! ```
+//! use poisson_ticker::{histogram::LatencyMap, summary_stats::SummaryStats, Error};
+//! use std::time::Duration;
+//!
+//! let empty = LatencyMap::new();
+//! let zero = Duration::from_secs(0);
+//! match SummaryStats::new(&empty, zero, zero, true, None) {
+//!     Err(Error::EmptyLatencyMap) => {}
+//!     other => panic!("unexpected: {:?}", other),
+//! }
+//! ```
+use super::histogram::RequestOutcome;
+use std::time::Duration;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Io(std::io::Error),
+    // opening or reading a named file failed
+    File {
+        path: String,
+        source: std::io::Error,
+    },
+    // a schedule or summary couldn't be (de)serialized
+    #[cfg(feature = "serde")]
+    Json {
+        path: Option<String>,
+        source: serde_json::Error,
+    },
+    // a latency log has a line that couldn't be parsed; lines are numbered from 1
+    MalformedLog {
+        path: String,
+        line: usize,
+        reason: String,
+    },
+    UnknownOutcome(String),
+    UnknownDistribution(String),
+    InvalidClassMix(String),
+    IdNotFound(usize),
+    InvalidIdRange {
+        start_id: usize,
+        end_id: usize,
+    },
+    EndBeforeStart {
+        id: usize,
+    },
+    // Ok and Timeout outcomes need a receive time
+    MissingEndTime {
+        id: usize,
+        outcome: RequestOutcome,
+    },
+    // Dropped and Shed outcomes can't have a receive time
+    UnexpectedEndTime {
+        id: usize,
+        outcome: RequestOutcome,
+    },
+    // a window that must count every response contains a dropped request
+    DroppedInWindow(usize),
+    // the window's end isn't after its start, or its length is zero
+    InvalidWindow,
+    // no requests were sent within the window
+    EmptyWindow,
+    EmptyLatencyMap,
+    WindowTooShort {
+        warmup: Duration,
+        cooldown: Duration,
+        experiment: Duration,
+    },
+    // quantiles of a `ManualHistogram` need `sort()` first
+    NotSorted,
+    QuantileNotFound(f64),
+    PrecisionMismatch(Option<u64>, Option<u64>),
+    NothingToMerge,
+    EmptySchedule,
+    ZeroInterarrival,
+    // a name that isn't one of the known kinds, e.g. an unknown report format
+    UnknownName {
+        kind: &'static str,
+        name: String,
+    },
+    // a configuration value or argument out of range
+    InvalidArgument(String),
+    // a histogram with no samples, e.g. when every request was dropped
+    EmptyHistogram,
+    IdOutOfRange {
+        id: usize,
+        capacity: usize,
+    },
+    // a time before the run anchor it is measured from
+    BeforeAnchor,
+    MalformedTimestamps(String),
+    InvalidObjective(String),
+    InvalidUrl(String),
+    // a peer broke the HTTP or coordinator protocol
+    Protocol(String),
+    Bind {
+        addr: String,
+        source: std::io::Error,
+    },
+    Connect {
+        addr: String,
+        source: std::io::Error,
+    },
+    Timeout(String),
+    // a thread panicked while holding a shared map's lock
+    LockPoisoned,
+    TaskFailed(String),
+    Unsupported(String),
+    // a failure of one client in a coordinated experiment
+    Client {
+        name: String,
+        source: Box<Error>,
+    },
+    // a failure at one rate of a sweep
+    AtRate {
+        rate: f64,
+        source: Box<Error>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::File { path, source } => write!(f, "Failed to access {}: {}", path, source),
+            #[cfg(feature = "serde")]
+            Error::Json { path: Some(p), source } => {
+                write!(f, "Failed to (de)serialize {}: {}", p, source)
+            }
+            #[cfg(feature = "serde")]
+            Error::Json { path: None, source } => write!(f, "Failed to (de)serialize: {}", source),
+            Error::MalformedLog { path, line, reason } => {
+                write!(f, "{}:{}: malformed line: {}", path, line, reason)
+            }
+            Error::UnknownOutcome(s) => write!(f, "{} request outcome unknown", s),
+            Error::UnknownDistribution(s) => write!(f, "{} distribution type unknown", s),
+            Error::InvalidClassMix(reason) => write!(f, "Invalid request class mix: {}", reason),
+            Error::IdNotFound(id) => write!(f, "ID not found in map: {}", id),
+            Error::InvalidIdRange { start_id, end_id } => write!(
+                f,
+                "start_id must be less than end_id: {}, {}",
+                start_id, end_id
+            ),
+            Error::EndBeforeStart { id } => write!(f, "End time is before start time: id {}", id),
+            Error::MissingEndTime { id, outcome } => {
+                write!(f, "Outcome {:?} requires an end time: id {}", outcome, id)
+            }
+            Error::UnexpectedEndTime { id, outcome } => {
+                write!(f, "{:?} requests cannot have an end time: id {}", outcome, id)
+            }
+            Error::DroppedInWindow(id) => write!(
+                f,
+                "ID has no recv time: {}; cannot count every response in a window with drops",
+                id
+            ),
+            Error::InvalidWindow => write!(f, "Window end must be after window start"),
+            Error::EmptyWindow => write!(f, "No requests were sent within the window"),
+            Error::EmptyLatencyMap => write!(f, "Latency map is empty"),
+            Error::WindowTooShort {
+                warmup,
+                cooldown,
+                experiment,
+            } => write!(
+                f,
+                "Warmup time ({:?}) plus cooldown time ({:?}) must be less than experiment time ({:?})",
+                warmup, cooldown, experiment
+            ),
+            Error::NotSorted => write!(f, "Cannot compute quantiles until sort() has been called"),
+            Error::QuantileNotFound(q) => write!(f, "Quantile not found: {:?}", q),
+            Error::PrecisionMismatch(a, b) => {
+                write!(f, "Histograms have different precisions: {:?}, {:?}", a, b)
+            }
+            Error::NothingToMerge => write!(f, "Cannot merge an empty list of summary stats"),
+            Error::EmptySchedule => write!(f, "Schedule is empty"),
+            Error::ZeroInterarrival => write!(f, "Schedule has no average interarrival"),
+            Error::UnknownName { kind, name } => write!(f, "{} {} unknown", name, kind),
+            Error::InvalidArgument(s) => write!(f, "{}", s),
+            Error::EmptyHistogram => write!(f, "Histogram has no samples"),
+            Error::IdOutOfRange { id, capacity } => write!(
+                f,
+                "Request ID {} out of range for recorder of capacity {}",
+                id, capacity
+            ),
+            Error::BeforeAnchor => write!(f, "Time is before the run anchor"),
+            Error::MalformedTimestamps(s) => write!(f, "Malformed timestamp log: {}", s),
+            Error::InvalidObjective(s) => write!(f, "Invalid objective: {}", s),
+            Error::InvalidUrl(s) => write!(f, "Invalid URL: {}", s),
+            Error::Protocol(s) => write!(f, "Protocol error: {}", s),
+            Error::Bind { addr, source } => write!(f, "Failed to bind {}: {}", addr, source),
+            Error::Connect { addr, source } => {
+                write!(f, "Failed to connect to {}: {}", addr, source)
+            }
+            Error::Timeout(s) => write!(f, "Timed out: {}", s),
+            Error::LockPoisoned => write!(f, "Lock poisoned"),
+            Error::TaskFailed(s) => write!(f, "Task failed: {}", s),
+            Error::Unsupported(s) => write!(f, "{}", s),
+            Error::Client { name, source } => write!(f, "Client {}: {}", name, source),
+            Error::AtRate { rate, source } => write!(f, "At rate {}: {}", rate, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e)
+            | Error::File { source: e, .. }
+            | Error::Bind { source: e, .. }
+            | Error::Connect { source: e, .. } => Some(e),
+            Error::Client { source, .. } | Error::AtRate { source, .. } => Some(source.as_ref()),
+            #[cfg(feature = "serde")]
+            Error::Json { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json {
+            path: None,
+            source: e,
+        }
+    }
+}
@@ -1,85 +1,421 @@
-use color_eyre::eyre::{bail, Result};
+use super::error::{Error, Result};
+use super::requests::RequestSchedule;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::Write;
-use std::time::{Duration, Instant};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How a request finished.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RequestOutcome {
+    /// A response arrived (within the timeout, if one is set).
+    Ok,
+    /// A response arrived, but after the timeout.
+    Timeout,
+    /// The server responded with an error code.
+    Error(u32),
+    /// No response arrived before the experiment ended.
+    Dropped,
+    /// The request was given up on and re-sent under a different ID.
+    Retried,
+    /// The client never sent the request because too many were already in flight.
+    Shed,
+}
+
+impl std::fmt::Display for RequestOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RequestOutcome::Ok => write!(f, "ok"),
+            RequestOutcome::Timeout => write!(f, "timeout"),
+            RequestOutcome::Error(code) => write!(f, "error:{}", code),
+            RequestOutcome::Dropped => write!(f, "dropped"),
+            RequestOutcome::Retried => write!(f, "retried"),
+            RequestOutcome::Shed => write!(f, "shed"),
+        }
+    }
+}
+
+impl std::str::FromStr for RequestOutcome {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<RequestOutcome> {
+        Ok(match s {
+            "ok" => RequestOutcome::Ok,
+            "timeout" => RequestOutcome::Timeout,
+            "dropped" => RequestOutcome::Dropped,
+            "retried" => RequestOutcome::Retried,
+            "shed" => RequestOutcome::Shed,
+            // `error <code>` is how logs written before the `error:<code>` spelling put it
+            x => match x
+                .strip_prefix("error:")
+                .or_else(|| x.strip_prefix("error "))
+            {
+                Some(code) => RequestOutcome::Error(
+                    code.trim()
+                        .parse()
+                        .map_err(|_| Error::UnknownOutcome(x.to_string()))?,
+                ),
+                None => return Err(Error::UnknownOutcome(x.to_string())),
+            },
+        })
+    }
+}
+
+/// A monotonic instant and the wall-clock time at the same moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunAnchor {
+    pub instant: Instant,
+    // nanoseconds since the unix epoch
+    pub unix_nanos: u64,
+}
+
+impl RunAnchor {
+    pub fn now() -> Self {
+        let instant = Instant::now();
+        RunAnchor {
+            instant,
+            unix_nanos: unix_nanos(SystemTime::now()),
+        }
+    }
+
+    /// An anchor at an earlier `instant`, with its wall-clock time inferred from now.
+    pub fn from_instant(instant: Instant) -> Self {
+        let now = RunAnchor::now();
+        let elapsed = now.instant.saturating_duration_since(instant);
+        RunAnchor {
+            instant,
+            unix_nanos: now.unix_nanos.saturating_sub(elapsed.as_nanos() as u64),
+        }
+    }
+
+    pub fn system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.unix_nanos)
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LatencyEntry {
+    pub sent: Instant,
+    // None if no response arrived before exp. ended
+    pub received: Option<Instant>,
+    pub outcome: RequestOutcome,
+    // request class, as assigned by the schedule; 0 if the workload has a single class
+    pub class: usize,
+    // time spent waiting for an in-flight limit before `sent`; None if sent on arrival
+    pub queue_delay: Option<Duration>,
+}
+
+impl LatencyEntry {
+    pub fn latency(&self) -> Option<Duration> {
+        self.received
+            .and_then(|recv| recv.checked_duration_since(self.sent))
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.outcome == RequestOutcome::Ok
+    }
+
+    /// Latency as seen from the request's arrival, including any client-side queueing.
+    pub fn latency_with_queueing(&self) -> Option<Duration> {
+        self.latency()
+            .map(|l| l + self.queue_delay.unwrap_or_default())
+    }
+}
 
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct LatencyMap {
-    // map from request ID to (start time, end time)
-    // end time is None if request was dropped before exp. ended
-    map: std::collections::BTreeMap<usize, (Instant, Option<Instant>)>,
+    // map from request ID to (start time, end time, outcome)
+    map: std::collections::BTreeMap<usize, LatencyEntry>,
+    // responses slower than this are classified as timeouts
+    timeout: Option<Duration>,
+    // wall-clock/monotonic reference for persisting absolute timestamps
+    anchor: Option<RunAnchor>,
 }
 
 impl LatencyMap {
     pub fn new() -> Self {
         LatencyMap {
             map: std::collections::BTreeMap::default(),
+            timeout: None,
+            anchor: None,
+        }
+    }
+
+    /// Classifies any response slower than `timeout` as [`RequestOutcome::Timeout`].
+    pub fn with_timeout(timeout: Duration) -> Self {
+        LatencyMap {
+            timeout: Some(timeout),
+            ..Self::new()
         }
     }
 
+    /// The run anchor, if one was set with `set_anchor`.
+    pub fn anchor(&self) -> Option<RunAnchor> {
+        self.anchor
+    }
+
+    /// Sets the reference point used when writing absolute timestamps.
+    ///
+    /// Capture it with `RunAnchor::now()` before the first request is sent.
+    pub fn set_anchor(&mut self, anchor: RunAnchor) {
+        self.anchor = Some(anchor);
+    }
+
     pub fn from_sent_and_recv_times(
         sent_times: &std::collections::HashMap<usize, Instant>,
         recv_times: &std::collections::HashMap<usize, Instant>,
     ) -> Result<Self> {
-        let mut map = std::collections::BTreeMap::new();
+        let mut map = LatencyMap::new();
         for (id, sent_time) in sent_times.iter() {
-            if let Some(recv_time) = recv_times.get(id) {
-                map.insert(*id, (*sent_time, Some(*recv_time)));
-            } else {
-                map.insert(*id, (*sent_time, None));
+            map.record(*id, *sent_time, recv_times.get(id).copied())?;
+        }
+        Ok(map)
+    }
+
+    /// Reads a log written by `log_to_file`.
+    ///
+    /// The log only has latencies, not send times, so request `id` is placed `id * interarrival`
+    /// after an arbitrary anchor. Time windows computed from the result therefore assume the
+    /// requests were sent at a constant rate.
+    pub fn from_log_file(path: &str, interarrival: Duration) -> Result<Self> {
+        let file = open(path)?;
+        let anchor = Instant::now();
+        let mut map = LatencyMap::new();
+        for (line_num, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| file_error(path, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let malformed = |reason: String| Error::MalformedLog {
+                path: path.to_string(),
+                line: line_num + 1,
+                reason,
+            };
+            let (id, latency, outcome) =
+                parse_log_line(&line).map_err(|r| malformed(format!("{} in `{}`", r, line)))?;
+            let offset_ns =
+                (interarrival.as_nanos().min(u64::MAX as u128) as u64).saturating_mul(id as u64);
+            let sent = anchor
+                .checked_add(Duration::from_nanos(offset_ns))
+                .ok_or_else(|| {
+                    malformed(format!("request {} is sent too late to represent", id))
+                })?;
+            let received = match latency {
+                Some(l) => Some(sent.checked_add(l).ok_or_else(|| {
+                    malformed(format!(
+                        "latency of request {} is too large to represent",
+                        id
+                    ))
+                })?),
+                None => None,
+            };
+            map.record_outcome(id, sent, received, outcome)
+                .map_err(|e| malformed(e.to_string()))?;
+        }
+        Ok(map)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sets the timeout and reclassifies already-recorded successful responses slower than it.
+    ///
+    /// Responses already classified as timeouts are not reclassified if the timeout grows.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+        for entry in self.map.values_mut() {
+            if entry.is_ok() && entry.latency().is_some_and(|l| l > timeout) {
+                entry.outcome = RequestOutcome::Timeout;
             }
         }
-        Ok(LatencyMap { map })
     }
 
+    /// Records a request. It is `Ok` if it has an end time, and `Dropped` otherwise.
     pub fn record(
         &mut self,
         request_id: usize,
         start: Instant,
         end: Option<Instant>,
+    ) -> Result<()> {
+        let outcome = if end.is_some() {
+            RequestOutcome::Ok
+        } else {
+            RequestOutcome::Dropped
+        };
+        self.record_outcome(request_id, start, end, outcome)
+    }
+
+    /// Records a request with an explicit outcome.
+    ///
+    /// An `Ok` outcome slower than the timeout is recorded as `Timeout`.
+    pub fn record_outcome(
+        &mut self,
+        request_id: usize,
+        start: Instant,
+        end: Option<Instant>,
+        outcome: RequestOutcome,
     ) -> Result<()> {
         if let Some(end_time) = end {
             if end_time.checked_duration_since(start).is_none() {
-                bail!(
-                    "End time is before start time: id {}, start {:?}, end {:?}",
-                    request_id,
-                    start,
-                    end_time
-                );
+                return Err(Error::EndBeforeStart { id: request_id });
             }
         }
-        self.map.insert(request_id, (start, end));
+
+        let outcome = match (outcome, end) {
+            (RequestOutcome::Ok, None) | (RequestOutcome::Timeout, None) => {
+                return Err(Error::MissingEndTime {
+                    id: request_id,
+                    outcome,
+                })
+            }
+            (RequestOutcome::Dropped, Some(_)) | (RequestOutcome::Shed, Some(_)) => {
+                return Err(Error::UnexpectedEndTime {
+                    id: request_id,
+                    outcome,
+                })
+            }
+            (RequestOutcome::Ok, Some(end_time)) => match self.timeout {
+                Some(timeout) if end_time.duration_since(start) > timeout => {
+                    RequestOutcome::Timeout
+                }
+                _ => RequestOutcome::Ok,
+            },
+            (o, _) => o,
+        };
+
+        // keep a class and queueing delay that were set before the request finished
+        let (class, queue_delay) = self
+            .map
+            .get(&request_id)
+            .map_or((0, None), |e| (e.class, e.queue_delay));
+        self.map.insert(
+            request_id,
+            LatencyEntry {
+                sent: start,
+                received: end,
+                outcome,
+                class,
+                queue_delay,
+            },
+        );
         Ok(())
     }
 
-    pub fn get(&self, request_id: usize) -> Option<&(Instant, Option<Instant>)> {
+    /// Sets the request class of an already-recorded request.
+    pub fn set_class(&mut self, request_id: usize, class: usize) -> Result<()> {
+        match self.map.get_mut(&request_id) {
+            Some(entry) => {
+                entry.class = class;
+                Ok(())
+            }
+            None => Err(Error::IdNotFound(request_id)),
+        }
+    }
+
+    /// Records how long an already-recorded request waited for an in-flight limit.
+    pub fn set_queue_delay(&mut self, request_id: usize, delay: Duration) -> Result<()> {
+        match self.map.get_mut(&request_id) {
+            Some(entry) => {
+                entry.queue_delay = Some(delay);
+                Ok(())
+            }
+            None => Err(Error::IdNotFound(request_id)),
+        }
+    }
+
+    /// Sets the class of every recorded request to the class of the schedule slot with the same
+    /// index.
+    pub fn set_classes_from_schedule(&mut self, schedule: &RequestSchedule) {
+        for (id, entry) in self.map.range_mut(..schedule.len()) {
+            entry.class = schedule.class(*id);
+        }
+    }
+
+    /// Distinct request classes present in the map.
+    pub fn classes(&self) -> std::collections::BTreeSet<usize> {
+        self.map.values().map(|e| e.class).collect()
+    }
+
+    pub fn get(&self, request_id: usize) -> Option<&LatencyEntry> {
         self.map.get(&request_id)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &LatencyEntry)> {
+        self.map.iter().map(|(id, entry)| (*id, entry))
+    }
+
     pub fn len(&self) -> usize {
         self.map.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Earliest send time recorded in the map.
+    pub fn first_send_time(&self) -> Option<Instant> {
+        self.map.values().map(|e| e.sent).min()
+    }
+
+    /// Latest send time recorded in the map.
+    pub fn last_send_time(&self) -> Option<Instant> {
+        self.map.values().map(|e| e.sent).max()
+    }
+
+    /// Requests sent within [start_time, end_time].
+    pub fn entries_in_time_range(
+        &self,
+        start_time: Instant,
+        end_time: Instant,
+    ) -> impl Iterator<Item = (usize, &LatencyEntry)> {
+        self.iter()
+            .filter(move |(_, e)| e.sent >= start_time && e.sent <= end_time)
+    }
+
+    /// Outcomes of the requests sent within [start_time, end_time].
+    pub fn outcomes_in_time_range(
+        &self,
+        start_time: Instant,
+        end_time: Instant,
+    ) -> impl Iterator<Item = RequestOutcome> + '_ {
+        self.entries_in_time_range(start_time, end_time)
+            .map(|(_, e)| e.outcome)
+    }
+
+    #[cfg(feature = "tracing")]
     pub fn dump(&self, msg: &str) {
         tracing::info!(msg, len = self.len());
     }
 
+    /// Logs one line per request: `id,latency_secs` for successful requests, with the outcome
+    /// appended for other requests that got a response, and `id, OUTCOME` for the rest.
+    ///
+    /// Outcomes are written as their `Display` form in upper case, e.g. `TIMEOUT`, `ERROR:503`,
+    /// or `DROPPED`.
     pub fn log_to_file(&self, path: &str) -> Result<()> {
         let mut file = File::create(path)?;
-        for (request_id, (start, recvd_time)) in self.map.iter() {
-            if let Some(end) = recvd_time {
-                let latency = end.checked_duration_since(*start);
-                if latency.is_none() {
-                    bail!(
-                        "End time is before start time: id {}, start {:?}, end {:?}",
-                        request_id,
-                        start,
-                        end
-                    );
-                }
-                writeln!(file, "{},{:?}", request_id, latency.unwrap().as_secs_f64())?;
-            } else {
-                writeln!(file, "{}, DROPPED", request_id)?;
+        for (request_id, entry) in self.map.iter() {
+            let latency = match entry.received {
+                Some(end) => match end.checked_duration_since(entry.sent) {
+                    Some(l) => Some(l.as_secs_f64()),
+                    None => return Err(Error::EndBeforeStart { id: *request_id }),
+                },
+                None => None,
+            };
+
+            let marker = entry.outcome.to_string().to_uppercase();
+            match (entry.outcome, latency) {
+                (RequestOutcome::Ok, Some(l)) => writeln!(file, "{},{:?}", request_id, l)?,
+                (_, Some(l)) => writeln!(file, "{},{:?},{}", request_id, l, marker)?,
+                (_, None) => writeln!(file, "{}, {}", request_id, marker)?,
             }
         }
         Ok(())
@@ -101,76 +437,57 @@ impl LatencyMap {
         // returns sent and received time in seconds
 
         if start_id >= end_id {
-            bail!("start_id must be less than end_id");
+            return Err(Error::InvalidIdRange { start_id, end_id });
         }
 
         if !self.map.contains_key(&start_id) {
-            bail!("start_id not found in map: {}", start_id);
+            return Err(Error::IdNotFound(start_id));
         }
 
         if !self.map.contains_key(&end_id) {
-            bail!("end_id not found in map : {}", end_id);
+            return Err(Error::IdNotFound(end_id));
         }
 
-        let mut histogram = ManualHistogram::new((end_id - start_id) as usize);
-        let start_time = self.map.get(&start_id).unwrap().0;
-        let last_sent_time = self.map.get(&end_id).unwrap().0;
+        let mut histogram = ManualHistogram::new(end_id - start_id);
+        let start_time = self.map.get(&start_id).unwrap().sent;
+        let last_sent_time = self.map.get(&end_id).unwrap().sent;
 
         if last_sent_time.checked_duration_since(start_time).is_none() {
-            bail!(
-                "End time is before start time: start_id {}, end_id {}",
-                start_id,
-                end_id
-            );
+            return Err(Error::InvalidIdRange { start_id, end_id });
         }
 
         if !use_time_window {
             // calculate time to send and receive all the data
 
             let mut max_end_time: Option<(Instant, Duration)> = None;
+            let mut num_completed = 0;
             for id in start_id..end_id {
                 let entry = self.map.get(&id);
-                if let Some((send_time, recv_time)) = entry {
-                    if recv_time.is_none() {
-                        bail!(
-                            "ID has no recv time: {}; cannot use id-based window with drops",
-                            id
-                        );
-                    }
-                    // record for latency histogram
-                    let rtt = recv_time.unwrap().duration_since(*send_time);
-                    histogram.record(rtt.as_nanos() as u64);
-
-                    if recv_time
-                        .unwrap()
-                        .checked_duration_since(start_time)
-                        .is_none()
-                    {
-                        bail!(
-                            "For id {}, recv_time is before send time of first id: {:?}, {:?}",
-                            id,
-                            recv_time.unwrap(),
-                            start_time
-                        );
+                if let Some(entry) = entry {
+                    let recv_time = match entry.received {
+                        Some(r) => r,
+                        None => return Err(Error::DroppedInWindow(id)),
+                    };
+                    num_completed += 1;
+                    // record for latency histogram; only successful responses count
+                    if entry.is_ok() {
+                        let rtt = recv_time.duration_since(entry.sent);
+                        histogram.record(rtt.as_nanos() as u64);
                     }
 
+                    let since_start = match recv_time.checked_duration_since(start_time) {
+                        Some(d) => d,
+                        None => return Err(Error::EndBeforeStart { id }),
+                    };
+
                     // update max end time
-                    if let Some((_cur_max_end, cur_max_time_since_start)) = max_end_time {
-                        if recv_time.unwrap().duration_since(start_time) > cur_max_time_since_start
-                        {
-                            max_end_time = Some((
-                                recv_time.unwrap(),
-                                recv_time.unwrap().duration_since(start_time),
-                            ));
-                        }
-                    } else {
-                        max_end_time = Some((
-                            recv_time.unwrap(),
-                            recv_time.unwrap().duration_since(start_time),
-                        ));
+                    match max_end_time {
+                        Some((_, cur_max_time_since_start))
+                            if since_start <= cur_max_time_since_start => {}
+                        _ => max_end_time = Some((recv_time, since_start)),
                     }
                 } else {
-                    bail!("ID not found in map: {}", id);
+                    return Err(Error::IdNotFound(id));
                 }
             }
             // return histogram, num sent, num received, sent time, received time
@@ -179,16 +496,10 @@ impl LatencyMap {
             // TODO: does num_sent = 1 + num_received?
             let num_sent = end_id - start_id;
             let num_received = histogram.len();
-            if histogram.len() != (end_id - start_id) {
-                bail!(
-                    "Histogram length does not match expected: {}, {}",
-                    histogram.len(),
-                    end_id - start_id
-                );
-            }
+            debug_assert_eq!(num_completed, end_id - start_id);
             let sent_time = last_sent_time.duration_since(start_time).as_secs_f64();
             let received_time = max_end_time.unwrap().1.as_secs_f64();
-            return Ok((histogram, num_sent, num_received, sent_time, received_time));
+            Ok((histogram, num_sent, num_received, sent_time, received_time))
         } else {
             let num_sent = end_id - start_id + 1;
             let mut num_received = 0;
@@ -196,22 +507,174 @@ impl LatencyMap {
             // consider time as between when start_id is sent and end_id is sent
             for id in start_id..end_id {
                 let entry = self.map.get(&id);
-                if let Some((send_time, recv_time_option)) = entry {
-                    if let Some(recv_time) = recv_time_option {
+                if let Some(entry) = entry {
+                    if let (Some(recv_time), true) = (entry.received, entry.is_ok()) {
                         // if receive time is within the sent time, count it
-                        if !last_sent_time.checked_duration_since(*recv_time).is_none() {
+                        if last_sent_time.checked_duration_since(recv_time).is_some() {
                             num_received += 1;
-                            let rtt = recv_time.duration_since(*send_time);
+                            let rtt = recv_time.duration_since(entry.sent);
                             histogram.record(rtt.as_nanos() as u64);
                         }
                     }
                 } else {
-                    bail!("ID not found in map: {}", id);
+                    return Err(Error::IdNotFound(id));
                 }
             }
             let sent_time = last_sent_time.duration_since(start_time).as_secs_f64();
             let received_time = last_sent_time.duration_since(start_time).as_secs_f64();
-            return Ok((histogram, num_sent, num_received, sent_time, received_time));
+            Ok((histogram, num_sent, num_received, sent_time, received_time))
+        }
+    }
+
+    pub fn histogram_from_time_range(
+        &self,
+        start_time: Instant,
+        end_time: Instant,
+        use_time_window: bool,
+    ) -> Result<(ManualHistogram, usize, usize, f64, f64)> {
+        self.histogram_from_time_range_inner(start_time, end_time, use_time_window, None)
+    }
+
+    /// Same as `histogram_from_time_range`, but only considers requests of the given class.
+    pub fn class_histogram_from_time_range(
+        &self,
+        start_time: Instant,
+        end_time: Instant,
+        use_time_window: bool,
+        class: usize,
+    ) -> Result<(ManualHistogram, usize, usize, f64, f64)> {
+        self.histogram_from_time_range_inner(start_time, end_time, use_time_window, Some(class))
+    }
+
+    fn histogram_from_time_range_inner(
+        &self,
+        start_time: Instant,
+        end_time: Instant,
+        use_time_window: bool,
+        class: Option<usize>,
+    ) -> Result<(ManualHistogram, usize, usize, f64, f64)> {
+        // Same as histogram_from_id_range, but the window is defined by send times rather than
+        // request IDs: only requests sent within [start_time, end_time] are considered.
+        // This does not assume IDs are dense or sent at a fixed rate.
+        // If use_time_window is true, only responses received by end_time are counted, and the
+        // sent and receive times are both the length of the window.
+        // Otherwise, every request in the window must have completed, and the receive time is
+        // measured until the last response arrives.
+        // Only successful responses are recorded in the histogram and counted as received.
+        // returns sent and received time in seconds
+
+        let window = match end_time.checked_duration_since(start_time) {
+            Some(w) if w > Duration::from_secs(0) => w,
+            _ => return Err(Error::InvalidWindow),
+        };
+
+        let in_window = self
+            .entries_in_time_range(start_time, end_time)
+            .filter(|(_, e)| class.is_none_or(|c| e.class == c));
+
+        let mut histogram = ManualHistogram::new(0);
+        let mut num_sent = 0;
+        if !use_time_window {
+            let mut max_end_time = start_time;
+            for (id, entry) in in_window {
+                num_sent += 1;
+                let recv_time = match (entry.received, entry.outcome) {
+                    (Some(r), _) => r,
+                    (None, RequestOutcome::Dropped) => return Err(Error::DroppedInWindow(id)),
+                    (None, _) => continue,
+                };
+                if entry.is_ok() {
+                    histogram.record(recv_time.duration_since(entry.sent).as_nanos() as u64);
+                }
+                if recv_time > max_end_time {
+                    max_end_time = recv_time;
+                }
+            }
+
+            if num_sent == 0 {
+                return Err(Error::EmptyWindow);
+            }
+
+            let num_received = histogram.len();
+            let sent_time = window.as_secs_f64();
+            let received_time = max_end_time.duration_since(start_time).as_secs_f64();
+            Ok((histogram, num_sent, num_received, sent_time, received_time))
+        } else {
+            let mut num_received = 0;
+            for (_, entry) in in_window {
+                num_sent += 1;
+                if let (Some(recv_time), true) = (entry.received, entry.is_ok()) {
+                    // if receive time is within the window, count it
+                    if recv_time <= end_time {
+                        num_received += 1;
+                        histogram.record(recv_time.duration_since(entry.sent).as_nanos() as u64);
+                    }
+                }
+            }
+
+            if num_sent == 0 {
+                return Err(Error::EmptyWindow);
+            }
+
+            let sent_time = window.as_secs_f64();
+            Ok((histogram, num_sent, num_received, sent_time, sent_time))
+        }
+    }
+}
+
+fn file_error(path: &str, source: std::io::Error) -> Error {
+    Error::File {
+        path: path.to_string(),
+        source,
+    }
+}
+
+fn open(path: &str) -> Result<File> {
+    File::open(path).map_err(|e| file_error(path, e))
+}
+
+// Parses one line of `LatencyMap::log_to_file` output into (id, latency, outcome).
+fn parse_log_line(
+    line: &str,
+) -> std::result::Result<(usize, Option<Duration>, RequestOutcome), String> {
+    let mut fields = line.split(',');
+    let id_field = fields.next().unwrap_or_default().trim();
+    let id: usize = id_field
+        .parse()
+        .map_err(|_| format!("invalid request id `{}`", id_field))?;
+    let second = match fields.next() {
+        Some(f) => f.trim(),
+        None => return Err("missing latency or outcome".to_string()),
+    };
+    let third = fields.next().map(str::trim);
+    if fields.next().is_some() {
+        return Err("too many fields".to_string());
+    }
+
+    let parse_outcome = |marker: &str| -> std::result::Result<RequestOutcome, String> {
+        marker
+            .to_ascii_lowercase()
+            .parse()
+            .map_err(|_| format!("unknown outcome `{}`", marker))
+    };
+
+    match (second, third) {
+        (marker, None) if marker.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+            Ok((id, None, parse_outcome(marker)?))
+        }
+        (latency, marker) => {
+            let secs: f64 = latency
+                .parse()
+                .map_err(|_| format!("invalid latency `{}`", latency))?;
+            let latency = Some(
+                Duration::try_from_secs_f64(secs)
+                    .map_err(|_| format!("invalid latency `{}`", latency))?,
+            );
+            let outcome = match marker {
+                None => RequestOutcome::Ok,
+                Some(m) => parse_outcome(m)?,
+            };
+            Ok((id, latency, outcome))
         }
     }
 }
@@ -228,19 +691,43 @@ impl ManualHistogram {
     pub fn new_from_vec(latencies: Vec<u64>) -> Self {
         ManualHistogram {
             current_count: latencies.len(),
-            latencies: latencies,
+            latencies,
             sorted_latencies: Vec::default(),
             is_sorted: false,
         }
     }
 
+    /// Reads a log written by `log_to_file`, one latency per line.
+    pub fn from_log_file(path: &str) -> Result<Self> {
+        let file = open(path)?;
+        let mut latencies = vec![];
+        for (line_num, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| file_error(path, e))?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            match trimmed.parse::<u64>() {
+                Ok(l) => latencies.push(l),
+                Err(_) => {
+                    return Err(Error::MalformedLog {
+                        path: path.to_string(),
+                        line: line_num + 1,
+                        reason: format!("expected a latency in nanoseconds in `{}`", line),
+                    })
+                }
+            }
+        }
+        Ok(Self::new_from_vec(latencies))
+    }
+
     pub fn latencies_vec(&self) -> &[u64] {
         &self.latencies[0..self.current_count]
     }
     pub fn new(num_values: usize) -> Self {
         ManualHistogram {
             current_count: 0,
-            latencies: vec![0u64; num_values as usize],
+            latencies: vec![0u64; num_values],
             sorted_latencies: Vec::default(),
             is_sorted: false,
         }
@@ -250,6 +737,10 @@ impl ManualHistogram {
         self.current_count
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.current_count == 0
+    }
+
     pub fn is_sorted(&self) -> bool {
         self.is_sorted
     }
@@ -270,8 +761,8 @@ impl ManualHistogram {
         Ok(())
     }
     pub fn value_at_quantile(&self, quantile: f64) -> Result<u64> {
-        if self.sorted_latencies.len() == 0 {
-            bail!("Cannot run value_at_quantile until sort() has been called.");
+        if self.sorted_latencies.is_empty() {
+            return Err(Error::NotSorted);
         }
         let index = (self.sorted_latencies.len() as f64 * quantile) as usize;
         Ok(self.sorted_latencies[index])
@@ -283,8 +774,10 @@ impl ManualHistogram {
     }
 
     pub fn log_truncated_to_file(&self, path: &str, start: usize) -> Result<()> {
+        #[cfg(feature = "tracing")]
         tracing::info!("Logging rtts to {}", path);
         let mut file = File::create(path)?;
+        #[cfg(feature = "tracing")]
         tracing::info!(len = self.current_count, "logging to {}", path);
         for idx in start..self.current_count {
             writeln!(file, "{}", self.latencies[idx])?;
@@ -292,9 +785,10 @@ impl ManualHistogram {
         Ok(())
     }
 
+    #[cfg(feature = "tracing")]
     fn mean(&self) -> Result<f64> {
-        if self.sorted_latencies.len() == 0 {
-            bail!("Cannot run value_at_quantile until sort() has been called.");
+        if self.sorted_latencies.is_empty() {
+            return Err(Error::NotSorted);
         }
 
         // TODO: use iterative algorithm that won't overflow
@@ -302,22 +796,25 @@ impl ManualHistogram {
         Ok(sum as f64 / (self.sorted_latencies.len() as f64))
     }
 
+    #[cfg(feature = "tracing")]
     fn max(&self) -> Result<u64> {
-        if self.sorted_latencies.len() == 0 {
-            bail!("Cannot run value_at_quantile until sort() has been called.");
+        if self.sorted_latencies.is_empty() {
+            return Err(Error::NotSorted);
         }
 
         Ok(self.sorted_latencies[self.sorted_latencies.len() - 1])
     }
 
+    #[cfg(feature = "tracing")]
     fn min(&self) -> Result<u64> {
-        if self.sorted_latencies.len() == 0 {
-            bail!("Cannot run value_at_quantile until sort() has been called.");
+        if self.sorted_latencies.is_empty() {
+            return Err(Error::NotSorted);
         }
 
         Ok(self.sorted_latencies[0])
     }
 
+    #[cfg(feature = "tracing")]
     pub fn dump(&self, msg: &str) -> Result<()> {
         if self.current_count == 0 {
             return Ok(());
@@ -325,18 +822,204 @@ impl ManualHistogram {
 
         tracing::info!(
             msg,
-            p5_ms = self.value_at_quantile(0.05)? / 1_000_000 as u64,
-            p25_ms = self.value_at_quantile(0.25)? / 1_000_000 as u64,
-            p50_ms = self.value_at_quantile(0.5)? / 1_000_000 as u64,
-            p75_ms = self.value_at_quantile(0.75)? / 1_000_000 as u64,
-            p95_ms = self.value_at_quantile(0.95)? / 1_000_000 as u64,
-            p99_ms = self.value_at_quantile(0.99)? / 1_000_000 as u64,
-            p999_ms = self.value_at_quantile(0.999)? / 1_000_000 as u64,
+            p5_ms = self.value_at_quantile(0.05)? / 1_000_000,
+            p25_ms = self.value_at_quantile(0.25)? / 1_000_000,
+            p50_ms = self.value_at_quantile(0.5)? / 1_000_000,
+            p75_ms = self.value_at_quantile(0.75)? / 1_000_000,
+            p95_ms = self.value_at_quantile(0.95)? / 1_000_000,
+            p99_ms = self.value_at_quantile(0.99)? / 1_000_000,
+            p999_ms = self.value_at_quantile(0.999)? / 1_000_000,
             requests_received = self.current_count,
-            min_ms = self.min()? / 1_000_000 as u64,
-            max_ms = self.max()? / 1_000_000 as u64,
+            min_ms = self.min()? / 1_000_000,
+            max_ms = self.max()? / 1_000_000,
             avg_ms = ?self.mean()? / 1_000_000.0f64
         );
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a log file under the temp directory, removed when dropped
+    struct TempLog(String);
+
+    impl TempLog {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!(
+                    "poisson-ticker-{}-{}.log",
+                    name,
+                    std::process::id()
+                ))
+                .display()
+                .to_string();
+            std::fs::write(&path, contents).unwrap();
+            TempLog(path)
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn read_log(name: &str, contents: &str, interarrival: Duration) -> Result<LatencyMap> {
+        let log = TempLog::new(name, contents);
+        LatencyMap::from_log_file(&log.0, interarrival)
+    }
+
+    fn malformed_line(result: Result<LatencyMap>) -> usize {
+        match result {
+            Err(Error::MalformedLog { line, .. }) => line,
+            other => panic!("expected a malformed log, got {:?}", other.map(|m| m.len())),
+        }
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let start = Instant::now();
+        let ms = Duration::from_millis(1);
+        let mut map = LatencyMap::new();
+        map.record(0, start, Some(start + 2 * ms)).unwrap();
+        map.record_outcome(
+            1,
+            start + ms,
+            Some(start + 3 * ms),
+            RequestOutcome::Error(503),
+        )
+        .unwrap();
+        map.record_outcome(2, start + 2 * ms, None, RequestOutcome::Error(7))
+            .unwrap();
+        map.record(3, start + 3 * ms, None).unwrap();
+        map.record_outcome(4, start + 4 * ms, None, RequestOutcome::Retried)
+            .unwrap();
+        map.record_outcome(5, start + 5 * ms, None, RequestOutcome::Shed)
+            .unwrap();
+
+        let log = TempLog::new("round-trip", "");
+        map.log_to_file(&log.0).unwrap();
+        let text = std::fs::read_to_string(&log.0).unwrap();
+        assert!(text.contains("\n1,0.002,ERROR:503\n"), "{}", text);
+        assert!(text.contains("\n2, ERROR:7\n"), "{}", text);
+        let read = LatencyMap::from_log_file(&log.0, ms).unwrap();
+        assert_eq!(read.len(), map.len());
+        for (id, entry) in map.iter() {
+            let other = read.get(id).unwrap();
+            assert_eq!(other.outcome, entry.outcome);
+            assert_eq!(other.latency(), entry.latency());
+        }
+        // send times are reconstructed from the interarrival
+        let first = read.first_send_time().unwrap();
+        assert_eq!(read.get(5).unwrap().sent - first, 5 * ms);
+    }
+
+    #[test]
+    fn outcome_display_round_trip() {
+        for outcome in [
+            RequestOutcome::Ok,
+            RequestOutcome::Timeout,
+            RequestOutcome::Error(503),
+            RequestOutcome::Dropped,
+            RequestOutcome::Retried,
+            RequestOutcome::Shed,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                outcome.to_string().parse::<RequestOutcome>().unwrap(),
+                *outcome
+            );
+        }
+        assert_eq!(
+            "error 404".parse::<RequestOutcome>().unwrap(),
+            RequestOutcome::Error(404)
+        );
+        assert!("error:x".parse::<RequestOutcome>().is_err());
+        assert!("lost".parse::<RequestOutcome>().is_err());
+    }
+
+    #[test]
+    fn parses_each_line_form() {
+        let map = read_log(
+            "forms",
+            "0,0.5\n1,0.25,TIMEOUT\n\n2, DROPPED\n3,0.1,ERROR 500\n4, ERROR 404\n",
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        assert_eq!(map.len(), 5);
+        assert_eq!(
+            map.get(0).unwrap().latency(),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(map.get(2).unwrap().outcome, RequestOutcome::Dropped);
+        assert_eq!(map.get(3).unwrap().outcome, RequestOutcome::Error(500));
+        assert_eq!(map.get(4).unwrap().latency(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let ms = Duration::from_millis(1);
+        let cases = [
+            ("id", "0,0.1\nx,0.1\n", 2),
+            ("latency", "0,fast\n", 1),
+            ("negative", "0,-1\n", 1),
+            ("nan", "0,NaN\n", 1),
+            ("huge-latency", "0,1e300\n", 1),
+            ("marker", "0, LOST\n", 1),
+            ("code", "0,0.1,ERROR abc\n", 1),
+            ("fields", "0,0.1,TIMEOUT,extra\n", 1),
+            ("missing", "0\n", 1),
+        ];
+        for (name, contents, line) in cases.iter() {
+            assert_eq!(
+                malformed_line(read_log(name, contents, ms)),
+                *line,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn large_ids_do_not_overflow() {
+        // `interarrival * id` used to truncate the id to 32 bits and overflow
+        let id = u32::MAX as usize + 1;
+        let map = read_log(
+            "large-id",
+            &format!("0,0.1\n{},0.1\n", id),
+            Duration::from_nanos(1),
+        )
+        .unwrap();
+        let first = map.first_send_time().unwrap();
+        assert_eq!(
+            map.get(id).unwrap().sent - first,
+            Duration::from_nanos(id as u64)
+        );
+
+        // offsets past the range of `Duration` nanoseconds saturate instead of panicking
+        read_log(
+            "far",
+            &format!("{},0.1\n", usize::MAX),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn manual_histogram_log() {
+        let log = TempLog::new("manual", "300\n100\n\n200\n");
+        let mut hist = ManualHistogram::from_log_file(&log.0).unwrap();
+        assert_eq!(hist.latencies_vec(), &[300, 100, 200]);
+        hist.sort().unwrap();
+        assert_eq!(hist.value_at_quantile(0.5).unwrap(), 200);
+
+        let bad = TempLog::new("manual-bad", "100\n1.5\n");
+        assert!(matches!(
+            ManualHistogram::from_log_file(&bad.0),
+            Err(Error::MalformedLog { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,935 @@
+//! An open-loop HTTP/1.1 load generator, and a test server for it.
+//!
+//! Requests are issued on the ticks of a [`SpinTicker`] and handed to a pool of keep-alive
+//! connections. Latency is measured from the tick, not from when a connection became free, so
+//! a slow server can't hide its queueing by slowing the client down (coordinated omission).
+//!
+//! Only plain `http://` is supported. Responses may be delimited by `Content-Length`, chunked
+//! encoding, or the connection closing, and bodies over 64 MiB are rejected.
+use super::error::{Error, Result};
+use super::histogram::{LatencyMap, RequestOutcome};
+use super::recorder::ConcurrentLatencyRecorder;
+use super::requests::RequestSchedule;
+use super::server::accept_loop;
+use super::summary_stats::SummaryStats;
+use super::SpinTicker;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Guards against runaway header sections.
+const MAX_HEADERS: usize = 100;
+// Guards against a bogus length making us allocate or read without bound.
+const MAX_BODY: usize = 64 << 20;
+
+fn body_too_large(len: impl std::fmt::Display) -> Error {
+    Error::Protocol(format!(
+        "body of {} bytes is larger than {} bytes",
+        len, MAX_BODY
+    ))
+}
+
+/// The parts of an `http://` URL the load generator needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseUrl {
+    pub host: String,
+    pub port: u16,
+    // without a trailing slash; empty for the root
+    pub path: String,
+}
+
+impl std::str::FromStr for BaseUrl {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<BaseUrl> {
+        let rest = match s.strip_prefix("http://") {
+            Some(r) => r,
+            None => {
+                return Err(Error::InvalidUrl(format!(
+                    "only http:// URLs are supported: {}",
+                    s
+                )))
+            }
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rfind(':') {
+            // a colon inside brackets belongs to an IPv6 address
+            Some(i) if !authority[i..].contains(']') => (
+                &authority[..i],
+                authority[i + 1..]
+                    .parse()
+                    .map_err(|_| Error::InvalidUrl(format!("invalid port in {}", s)))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl(format!("no host in {}", s)));
+        }
+        Ok(BaseUrl {
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl BaseUrl {
+    fn host_header(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            p => format!("{}:{}", self.host, p),
+        }
+    }
+}
+
+/// The method, path, and headers sent with every request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestTemplate {
+    pub method: String,
+    // appended to the base URL's path
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestTemplate {
+    pub fn new(method: &str, path: &str) -> Self {
+        RequestTemplate {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        Self::new("GET", path)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Serializes a request with this template and `body`.
+    fn render(&self, base: &BaseUrl, body: &[u8]) -> Vec<u8> {
+        let mut req = format!(
+            "{} {}{} HTTP/1.1\r\n",
+            self.method,
+            base.path,
+            match self.path.as_str() {
+                "" => "/",
+                p => p,
+            }
+        );
+        let has = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(h, _)| h.eq_ignore_ascii_case(name))
+        };
+        if !has("host") {
+            req.push_str(&format!("Host: {}\r\n", base.host_header()));
+        }
+        for (name, value) in self.headers.iter() {
+            req.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // bodyless GETs are the only requests that conventionally omit the length
+        let needs_length = !body.is_empty() || self.method != "GET";
+        if needs_length && !has("content-length") {
+            req.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        req.push_str("\r\n");
+
+        let mut req = req.into_bytes();
+        req.extend_from_slice(body);
+        req
+    }
+}
+
+/// The same body for every request.
+pub fn fixed_body(body: Vec<u8>) -> impl FnMut(usize) -> Vec<u8> {
+    move |_| body.clone()
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpLoadConfig {
+    pub base_url: BaseUrl,
+    // requests are handed to the first free connection in a pool of this size
+    pub connections: usize,
+    // stop issuing requests after this long, even if the schedule has requests left
+    pub duration: Duration,
+    // how long to wait for outstanding responses after the last request is issued;
+    // requests still outstanding afterwards are counted as dropped
+    pub drain: Duration,
+    // responses slower than this are abandoned, and their connection is reopened
+    pub timeout: Option<Duration>,
+    pub warmup: Duration,
+    pub cooldown: Duration,
+    pub histogram_precision: Option<u64>,
+}
+
+impl HttpLoadConfig {
+    pub fn new(base_url: BaseUrl) -> Self {
+        HttpLoadConfig {
+            base_url,
+            connections: 1,
+            duration: Duration::from_secs(10),
+            drain: Duration::from_secs(1),
+            timeout: None,
+            warmup: Duration::from_secs(1),
+            cooldown: Duration::from_secs(1),
+            histogram_precision: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpLoadResult {
+    // 4xx and 5xx responses are recorded as errors with the status as their code
+    pub latencies: LatencyMap,
+    pub stats: SummaryStats,
+    // Map from status code to number of responses, over the whole run
+    pub status_codes: BTreeMap<u16, usize>,
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(h, _)| h.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Reads a start line and headers. Returns `None` if the connection closed before a new message.
+async fn read_head<R>(r: &mut R) -> Result<Option<(String, Vec<(String, String)>)>>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut line = String::new();
+    if r.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let start = line.trim_end().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line).await? == 0 {
+            return Err(Error::Protocol("connection closed in headers".to_string()));
+        }
+        let l = line.trim_end();
+        if l.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(Error::Protocol(format!(
+                "more than {} headers",
+                MAX_HEADERS
+            )));
+        }
+        match l.split_once(':') {
+            Some((name, value)) => {
+                headers.push((name.trim().to_string(), value.trim().to_string()))
+            }
+            None => return Err(Error::Protocol(format!("malformed header: {}", l))),
+        }
+    }
+    Ok(Some((start, headers)))
+}
+
+/// Reads the body described by `headers`. Without a length or chunked encoding, the body runs
+/// until the connection closes only if `until_close` is set, and is empty otherwise.
+async fn read_body<R>(r: &mut R, headers: &[(String, String)], until_close: bool) -> Result<Vec<u8>>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut body = Vec::new();
+    if header(headers, "transfer-encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked")) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            r.read_line(&mut line).await?;
+            let size = line.trim_end().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| Error::Protocol(format!("invalid chunk size: {:?}", line)))?;
+            if size == 0 {
+                break;
+            }
+            if size > MAX_BODY - body.len() {
+                return Err(body_too_large(format!("at least {}", body.len() + size)));
+            }
+            let start = body.len();
+            body.resize(start + size + 2, 0);
+            r.read_exact(&mut body[start..]).await?;
+            body.truncate(start + size);
+        }
+        // trailers
+        loop {
+            line.clear();
+            if r.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+        }
+    } else if let Some(len) = header(headers, "content-length") {
+        let len: u64 = len
+            .parse()
+            .map_err(|_| Error::Protocol(format!("invalid Content-Length: {}", len)))?;
+        if len > MAX_BODY as u64 {
+            return Err(body_too_large(len));
+        }
+        body.resize(len as usize, 0);
+        r.read_exact(&mut body).await?;
+    } else if until_close {
+        r.take(MAX_BODY as u64 + 1).read_to_end(&mut body).await?;
+        if body.len() > MAX_BODY {
+            return Err(body_too_large(format!("more than {}", MAX_BODY)));
+        }
+    }
+    Ok(body)
+}
+
+struct Response {
+    status: u16,
+    // the server will close the connection after this response
+    close: bool,
+}
+
+async fn read_response<R>(r: &mut R, head_request: bool) -> Result<Response>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let (status_line, headers) = read_head(r)
+        .await?
+        .ok_or_else(|| Error::Protocol("connection closed before response".to_string()))?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") {
+        return Err(Error::Protocol(format!(
+            "not an HTTP/1.x response: {}",
+            status_line
+        )));
+    }
+    let status: u16 = parts
+        .next()
+        .unwrap_or("")
+        .parse()
+        .map_err(|_| Error::Protocol(format!("invalid status line: {}", status_line)))?;
+
+    let close = version == "HTTP/1.0"
+        || header(&headers, "connection").is_some_and(|c| c.eq_ignore_ascii_case("close"));
+    let has_body = !head_request && !(100..200).contains(&status) && status != 204 && status != 304;
+    if has_body {
+        read_body(r, &headers, close).await?;
+    }
+    Ok(Response { status, close })
+}
+
+fn outcome(status: u16) -> RequestOutcome {
+    match status {
+        400..=599 => RequestOutcome::Error(status as u32),
+        _ => RequestOutcome::Ok,
+    }
+}
+
+struct Job {
+    id: usize,
+    request: Vec<u8>,
+}
+
+// State shared by the connections of one run.
+struct Pool {
+    target: SocketAddr,
+    jobs: tokio::sync::Mutex<mpsc::UnboundedReceiver<Job>>,
+    recorder: Arc<ConcurrentLatencyRecorder>,
+    status_codes: Mutex<BTreeMap<u16, usize>>,
+    timeout: Option<Duration>,
+    head_requests: bool,
+}
+
+impl Pool {
+    async fn connect(&self) -> Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect(self.target).await?;
+        stream.set_nodelay(true)?;
+        Ok(BufReader::new(stream))
+    }
+
+    async fn exchange(&self, conn: &mut BufReader<TcpStream>, request: &[u8]) -> Result<Response> {
+        conn.get_mut().write_all(request).await?;
+        read_response(conn, self.head_requests).await
+    }
+
+    /// Sends jobs one at a time over a keep-alive connection, reconnecting as needed.
+    async fn run_connection(&self) {
+        let mut conn = None;
+        loop {
+            let job = match self.jobs.lock().await.recv().await {
+                Some(j) => j,
+                None => return,
+            };
+
+            if conn.is_none() {
+                match self.connect().await {
+                    Ok(c) => conn = Some(c),
+                    Err(e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(id = job.id, err = %e, "Connect failed");
+                        let _ = &e;
+                        self.record(job.id, RequestOutcome::Dropped, None);
+                        continue;
+                    }
+                }
+            }
+
+            let c = conn.as_mut().unwrap();
+            let res = match self.timeout {
+                Some(t) => tokio::time::timeout(t, self.exchange(c, &job.request))
+                    .await
+                    .ok(),
+                None => Some(self.exchange(c, &job.request).await),
+            };
+            match res {
+                Some(Ok(resp)) => {
+                    if let Ok(mut codes) = self.status_codes.lock() {
+                        *codes.entry(resp.status).or_default() += 1;
+                    }
+                    self.record(job.id, outcome(resp.status), Some(Instant::now()));
+                    if resp.close {
+                        conn = None;
+                    }
+                }
+                Some(Err(e)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(id = job.id, err = %e, "Request failed");
+                    let _ = &e;
+                    self.record(job.id, RequestOutcome::Dropped, None);
+                    // the connection is in an unknown state
+                    conn = None;
+                }
+                None => {
+                    self.record(job.id, RequestOutcome::Timeout, Some(Instant::now()));
+                    // a late response would be read as the next request's
+                    conn = None;
+                }
+            }
+        }
+    }
+
+    fn record(&self, id: usize, outcome: RequestOutcome, end: Option<Instant>) {
+        let res = match end {
+            Some(end) => self.recorder.record_finished_at(id, end, outcome),
+            None => self.recorder.record_outcome(id, outcome),
+        };
+        if let Err(e) = res {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(id, err = %e, "Failed to record response");
+            let _ = &e;
+        }
+    }
+}
+
+/// Issues one request per tick of a [`SpinTicker`] over `schedule`, and records the responses.
+///
+/// `body` is called with each request ID; use [`fixed_body`] to send the same body every time.
+pub async fn run_http_load<B>(
+    config: &HttpLoadConfig,
+    schedule: RequestSchedule,
+    template: &RequestTemplate,
+    mut body: B,
+) -> Result<HttpLoadResult>
+where
+    B: FnMut(usize) -> Vec<u8>,
+{
+    if config.connections == 0 {
+        return Err(Error::InvalidArgument(
+            "HTTP load generator needs at least one connection".to_string(),
+        ));
+    }
+
+    let url = &config.base_url;
+    let host = url.host.trim_start_matches('[').trim_end_matches(']');
+    let target = tokio::net::lookup_host((host, url.port))
+        .await
+        .map_err(|source| Error::Connect {
+            addr: url.host.clone(),
+            source,
+        })?
+        .next()
+        .ok_or_else(|| Error::InvalidUrl(format!("no addresses for {}", url.host)))?;
+
+    let mut recorder = ConcurrentLatencyRecorder::from_schedule(&schedule);
+    if let Some(timeout) = config.timeout {
+        recorder = recorder.with_timeout(timeout);
+    }
+    let recorder = Arc::new(recorder);
+    let (jobs, rx) = mpsc::unbounded_channel();
+    let pool = Arc::new(Pool {
+        target,
+        jobs: tokio::sync::Mutex::new(rx),
+        recorder: Arc::clone(&recorder),
+        status_codes: Mutex::new(BTreeMap::new()),
+        timeout: config.timeout,
+        head_requests: template.method.eq_ignore_ascii_case("HEAD"),
+    });
+    let connections: Vec<_> = (0..config.connections)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+            tokio::spawn(async move { pool.run_connection().await })
+        })
+        .collect();
+
+    let mut ticker = SpinTicker::new(schedule, config.duration);
+    let mut sent = 0;
+    while (&mut ticker).await.is_some() {
+        let id = sent;
+        sent += 1;
+        let request = template.render(url, &body(id));
+        // requests waiting for a free connection are already late, and their latency shows it
+        recorder.record_sent(id)?;
+        if jobs.send(Job { id, request }).is_err() {
+            return Err(Error::TaskFailed(
+                "HTTP connections exited early".to_string(),
+            ));
+        }
+    }
+    drop(jobs);
+
+    let drained = tokio::time::timeout(config.drain, async {
+        let mut next = 0;
+        while next < sent {
+            if recorder.is_finished(next) {
+                next += 1;
+            } else {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+    })
+    .await;
+    if drained.is_err() {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(sent, "Requests still outstanding after drain period");
+    }
+    for c in connections {
+        c.abort();
+    }
+
+    let latencies = recorder.to_latency_map()?;
+    let stats = SummaryStats::new(
+        &latencies,
+        config.warmup,
+        config.cooldown,
+        true,
+        config.histogram_precision,
+    )?;
+    let status_codes = pool
+        .status_codes
+        .lock()
+        .map_err(|_| Error::LockPoisoned)?
+        .clone();
+    Ok(HttpLoadResult {
+        latencies,
+        stats,
+        status_codes,
+    })
+}
+
+/// A request received by an [`HttpTestServer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// The response an [`HttpTestServer`] handler returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+    // the server waits this long before responding
+    pub delay: Duration,
+}
+
+impl TestResponse {
+    pub fn ok(body: Vec<u8>) -> Self {
+        TestResponse {
+            status: 200,
+            body,
+            delay: Duration::from_secs(0),
+        }
+    }
+}
+
+type Handler = dyn Fn(&TestRequest) -> TestResponse + Send + Sync;
+
+/// A keep-alive HTTP/1.1 server for testing the load generator.
+///
+/// The server runs on the tokio runtime until it is dropped.
+pub struct HttpTestServer {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl HttpTestServer {
+    /// Binds to `addr` and answers every request with `200 OK` and the request's body.
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::bind_with(addr, |req| TestResponse::ok(req.body.clone())).await
+    }
+
+    /// Binds to `addr` and answers each request with `handler`'s response.
+    pub async fn bind_with<F>(addr: SocketAddr, handler: F) -> Result<Self>
+    where
+        F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|source| Error::Bind {
+                addr: addr.to_string(),
+                source,
+            })?;
+        let local_addr = listener.local_addr()?;
+        let task = tokio::spawn(serve(listener, Arc::new(handler)));
+        Ok(HttpTestServer { local_addr, task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The server's address as a base URL.
+    pub fn base_url(&self) -> BaseUrl {
+        BaseUrl {
+            host: match self.local_addr {
+                SocketAddr::V4(a) => a.ip().to_string(),
+                SocketAddr::V6(a) => format!("[{}]", a.ip()),
+            },
+            port: self.local_addr.port(),
+            path: String::new(),
+        }
+    }
+}
+
+impl Drop for HttpTestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, handler: Arc<Handler>) {
+    accept_loop(listener, |stream, from| {
+        let handler = Arc::clone(&handler);
+        async move {
+            let _ = &from;
+            if let Err(e) = serve_connection(stream, handler).await {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(err = %e, ?from, "Test server connection failed");
+                let _ = &e;
+            }
+        }
+    })
+    .await
+}
+
+async fn serve_connection(stream: TcpStream, handler: Arc<Handler>) -> Result<()> {
+    let mut conn = BufReader::new(stream);
+    while let Some((request_line, headers)) = read_head(&mut conn).await? {
+        let mut parts = request_line.split(' ');
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(p), Some(v)) if v.starts_with("HTTP/1.") => (m, p),
+            _ => {
+                return Err(Error::Protocol(format!(
+                    "malformed request line: {}",
+                    request_line
+                )))
+            }
+        };
+        let body = read_body(&mut conn, &headers, false).await?;
+        let close = header(&headers, "connection").is_some_and(|c| c.eq_ignore_ascii_case("close"));
+        let req = TestRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers,
+            body,
+        };
+
+        let resp = handler(&req);
+        if resp.delay > Duration::from_secs(0) {
+            tokio::time::sleep(resp.delay).await;
+        }
+        let mut out = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n{}\r\n",
+            resp.status,
+            reason(resp.status),
+            resp.body.len(),
+            if close { "Connection: close\r\n" } else { "" },
+        )
+        .into_bytes();
+        if req.method != "HEAD" {
+            out.extend_from_slice(&resp.body);
+        }
+        conn.get_mut().write_all(&out).await?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::DistributionType;
+
+    async fn parse(response: &str) -> Result<(u16, bool)> {
+        let mut r = response.as_bytes();
+        let resp = read_response(&mut r, false).await?;
+        // the whole response was consumed
+        assert!(r.is_empty(), "left over: {:?}", String::from_utf8_lossy(r));
+        Ok((resp.status, resp.close))
+    }
+
+    async fn body(headers: &[(&str, &str)], raw: &str) -> Result<Vec<u8>> {
+        let headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(h, v)| (h.to_string(), v.to_string()))
+            .collect();
+        read_body(&mut raw.as_bytes(), &headers, true).await
+    }
+
+    #[test]
+    fn parses_base_urls() {
+        let url: BaseUrl = "http://example.com:8080/api/".parse().unwrap();
+        assert_eq!(
+            url,
+            BaseUrl {
+                host: "example.com".to_string(),
+                port: 8080,
+                path: "/api".to_string(),
+            }
+        );
+        let url: BaseUrl = "http://[::1]".parse().unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 80));
+        assert_eq!(url.host_header(), "[::1]");
+        assert!("https://example.com".parse::<BaseUrl>().is_err());
+        assert!("http://:80".parse::<BaseUrl>().is_err());
+        assert!("http://example.com:http".parse::<BaseUrl>().is_err());
+    }
+
+    #[test]
+    fn renders_requests() {
+        let url: BaseUrl = "http://localhost:8080/api".parse().unwrap();
+        let get = RequestTemplate::get("/items").with_header("Accept", "*/*");
+        assert_eq!(
+            String::from_utf8(get.render(&url, b"")).unwrap(),
+            "GET /api/items HTTP/1.1\r\nHost: localhost:8080\r\nAccept: */*\r\n\r\n"
+        );
+        let post = RequestTemplate::new("POST", "").with_header("host", "other");
+        assert_eq!(
+            String::from_utf8(post.render(&url, b"hi")).unwrap(),
+            "POST /api/ HTTP/1.1\r\nhost: other\r\nContent-Length: 2\r\n\r\nhi"
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_delimited_responses() {
+        assert_eq!(
+            parse("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .await
+                .unwrap(),
+            (200, false)
+        );
+        assert_eq!(
+            parse("HTTP/1.1 503 Busy\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r\n0\r\nTrailer: 1\r\n\r\n")
+                .await
+                .unwrap(),
+            (503, false)
+        );
+        // no length: the body runs until the server closes
+        assert_eq!(
+            parse("HTTP/1.0 200 OK\r\n\r\nall of it").await.unwrap(),
+            (200, true)
+        );
+        assert_eq!(
+            parse("HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap(),
+            (204, true)
+        );
+    }
+
+    #[tokio::test]
+    async fn head_responses_have_no_body() {
+        let mut r = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n".as_bytes();
+        assert_eq!(read_response(&mut r, true).await.unwrap().status, 200);
+    }
+
+    #[tokio::test]
+    async fn reads_bodies() {
+        assert_eq!(
+            body(
+                &[("transfer-encoding", "Chunked")],
+                "2\r\nab\r\n1\r\nc\r\n0\r\n\r\n"
+            )
+            .await
+            .unwrap(),
+            b"abc"
+        );
+        assert_eq!(
+            body(&[("Content-Length", "3")], "abcdef").await.unwrap(),
+            b"abc"
+        );
+        assert_eq!(body(&[], "abcdef").await.unwrap(), b"abcdef");
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_responses() {
+        for response in [
+            "",
+            "SPDY/3 200 OK\r\n\r\n",
+            "HTTP/1.1 OK\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nno colon\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        ]
+        .iter()
+        {
+            assert!(parse(response).await.is_err(), "{:?}", response);
+        }
+
+        let many: String = (0..=MAX_HEADERS)
+            .map(|i| format!("X-{}: 1\r\n", i))
+            .collect();
+        let response = format!("HTTP/1.1 200 OK\r\n{}\r\n", many);
+        assert!(matches!(parse(&response).await, Err(Error::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_bodies() {
+        let huge = (MAX_BODY + 1).to_string();
+        assert!(matches!(
+            body(&[("Content-Length", &huge)], "").await,
+            Err(Error::Protocol(_))
+        ));
+        assert!(matches!(
+            body(&[("Content-Length", "99999999999999999999999")], "").await,
+            Err(Error::Protocol(_))
+        ));
+        let chunked = [("Transfer-Encoding", "chunked")];
+        let chunk = format!("{:x}\r\n", MAX_BODY + 1);
+        assert!(matches!(
+            body(&chunked, &chunk).await,
+            Err(Error::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn statuses_map_to_outcomes() {
+        assert_eq!(outcome(200), RequestOutcome::Ok);
+        assert_eq!(outcome(302), RequestOutcome::Ok);
+        assert_eq!(outcome(404), RequestOutcome::Error(404));
+        assert_eq!(outcome(503), RequestOutcome::Error(503));
+    }
+
+    fn config(server: &HttpTestServer) -> HttpLoadConfig {
+        HttpLoadConfig {
+            connections: 4,
+            duration: Duration::from_millis(200),
+            drain: Duration::from_millis(500),
+            warmup: Duration::from_secs(0),
+            cooldown: Duration::from_secs(0),
+            ..HttpLoadConfig::new(server.base_url())
+        }
+    }
+
+    fn schedule() -> RequestSchedule {
+        RequestSchedule::new_seeded(50, 500.0, DistributionType::Uniform, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn load_against_the_test_server() {
+        let server = HttpTestServer::bind_with("127.0.0.1:0".parse().unwrap(), |req| {
+            match req.body.first() {
+                Some(b) if b % 5 == 0 => TestResponse {
+                    status: 503,
+                    ..TestResponse::ok(vec![])
+                },
+                _ => TestResponse::ok(req.body.clone()),
+            }
+        })
+        .await
+        .unwrap();
+        let template = RequestTemplate::new("POST", "/echo");
+        let result = run_http_load(&config(&server), schedule(), &template, |id| vec![id as u8])
+            .await
+            .unwrap();
+        assert_eq!(result.status_codes.get(&200), Some(&40));
+        assert_eq!(result.status_codes.get(&503), Some(&10));
+        assert_eq!(result.stats.outcomes.ok, 40);
+        assert_eq!(result.stats.outcomes.errors.get(&503), Some(&10));
+    }
+
+    #[tokio::test]
+    async fn slow_responses_time_out() {
+        let server = HttpTestServer::bind_with("127.0.0.1:0".parse().unwrap(), |_| TestResponse {
+            delay: Duration::from_secs(1),
+            ..TestResponse::ok(vec![])
+        })
+        .await
+        .unwrap();
+        let config = HttpLoadConfig {
+            timeout: Some(Duration::from_millis(10)),
+            ..config(&server)
+        };
+        let schedule = RequestSchedule::new_seeded(4, 100.0, DistributionType::Uniform, 0).unwrap();
+        let result = run_http_load(
+            &config,
+            schedule,
+            &RequestTemplate::get("/"),
+            fixed_body(vec![]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.stats.outcomes.timeout, 4);
+    }
+
+    #[tokio::test]
+    async fn needs_a_connection() {
+        let server = HttpTestServer::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let config = HttpLoadConfig {
+            connections: 0,
+            ..config(&server)
+        };
+        assert!(matches!(
+            run_http_load(
+                &config,
+                schedule(),
+                &RequestTemplate::get("/"),
+                fixed_body(vec![])
+            )
+            .await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,368 @@
+//! A [`tower`] middleware that issues requests to the inner service on the ticks of a
+//! [`SpinTicker`], turning any tower client into an open-loop load generator.
+//!
+//! Every clone of the service produced by a [`PoissonPaceLayer`] shares one ticker, so the
+//! combined request rate follows the schedule no matter how many clones there are. Each request
+//! is recorded from its tick, not from when the inner service became ready, so time spent
+//! waiting on a busy service counts towards its latency. Requests that fail are recorded as
+//! `Error(0)`.
+//!
+//! # Example
+//! ```rust
+//! # #[tokio::main]
+//! # async fn main() {
+//! use poisson_ticker::layer::PoissonPaceLayer;
+//! use poisson_ticker::requests::{DistributionType, RequestSchedule};
+//! use std::time::Duration;
+//! use tower::{Service, ServiceExt};
+//!
+//! let schedule = RequestSchedule::new(20, 2000., DistributionType::Exponential).unwrap();
+//! let layer = PoissonPaceLayer::new(schedule, Duration::from_secs(10));
+//! let handle = layer.handle();
+//! let mut svc = tower::ServiceBuilder::new()
+//!     .layer(layer)
+//!     .service_fn(|x: u32| async move { Ok::<_, std::convert::Infallible>(x * 2) });
+//!
+//! // the schedule runs out after 20 requests
+//! while let Ok(svc) = svc.ready().await {
+//!     svc.call(21).await.unwrap();
+//! }
+//! let stats = handle.summary_stats(Duration::from_secs(0), Duration::from_secs(0), false, None).unwrap();
+//! assert_eq!(stats.total_objects_sent, 20);
+//! assert_eq!(stats.outcomes.ok, 20);
+//! # }
+//! ```
+use super::error::{Error, Result};
+use super::histogram::{LatencyMap, RequestOutcome};
+use super::requests::RequestSchedule;
+use super::summary_stats::SummaryStats;
+use super::{SpinTicker, SpinTimer, Timer};
+use core::task::{Context, Poll};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tower::{BoxError, Layer, Service};
+
+/// Returned from `poll_ready` once the schedule has no ticks left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleExhausted;
+
+impl std::fmt::Display for ScheduleExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Request schedule exhausted")
+    }
+}
+
+impl std::error::Error for ScheduleExhausted {}
+
+// State shared by the layer, every service it produces, and the handle.
+struct Shared<T> {
+    ticker: tokio::sync::Mutex<SpinTicker<T>>,
+    latencies: Arc<Mutex<LatencyMap>>,
+    next_id: AtomicUsize,
+}
+
+/// Reads the latencies recorded by a [`PoissonPaceLayer`]'s services, during or after a run.
+#[derive(Clone)]
+pub struct PaceHandle {
+    latencies: Arc<Mutex<LatencyMap>>,
+}
+
+impl PaceHandle {
+    /// A snapshot of every request issued so far; requests still in flight are dropped.
+    pub fn latencies(&self) -> Result<LatencyMap> {
+        self.latencies
+            .lock()
+            .map(|l| l.clone())
+            .map_err(|_| Error::LockPoisoned)
+    }
+
+    pub fn summary_stats(
+        &self,
+        warmup: Duration,
+        cooldown: Duration,
+        use_time_window: bool,
+        histogram_precision: Option<u64>,
+    ) -> Result<SummaryStats> {
+        SummaryStats::new(
+            &self.latencies()?,
+            warmup,
+            cooldown,
+            use_time_window,
+            histogram_precision,
+        )
+    }
+}
+
+pub struct PoissonPaceLayer<T = SpinTimer> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for PoissonPaceLayer<T> {
+    fn clone(&self) -> Self {
+        PoissonPaceLayer {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl PoissonPaceLayer<SpinTimer> {
+    pub fn new(schedule: RequestSchedule, end_time: Duration) -> Self {
+        Self::from_ticker(SpinTicker::new(schedule, end_time))
+    }
+}
+
+impl<T: Timer + Unpin + Send + 'static> PoissonPaceLayer<T> {
+    pub fn from_ticker(ticker: SpinTicker<T>) -> Self {
+        PoissonPaceLayer {
+            shared: Arc::new(Shared {
+                ticker: tokio::sync::Mutex::new(ticker),
+                latencies: Arc::new(Mutex::new(LatencyMap::new())),
+                next_id: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn handle(&self) -> PaceHandle {
+        PaceHandle {
+            latencies: Arc::clone(&self.shared.latencies),
+        }
+    }
+}
+
+impl<S, T> Layer<S> for PoissonPaceLayer<T> {
+    type Service = PoissonPace<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PoissonPace {
+            inner,
+            shared: Arc::clone(&self.shared),
+            waiting: None,
+            tick: None,
+        }
+    }
+}
+
+/// Service returned by [`PoissonPaceLayer`].
+///
+/// `poll_ready` takes a tick from the shared schedule and holds it for the next `call`, as the
+/// tower contract requires `call` to follow a successful `poll_ready`. A service that is polled
+/// ready and then dropped without being called loses its tick: it's gone from the schedule but
+/// no request is recorded for it.
+pub struct PoissonPace<S, T = SpinTimer> {
+    inner: S,
+    shared: Arc<Shared<T>>,
+    // the pending wait for this service's next tick
+    waiting: Option<Pin<Box<dyn Future<Output = Option<Instant>> + Send>>>,
+    // the tick the next call will use
+    tick: Option<Instant>,
+}
+
+impl<S: Clone, T> Clone for PoissonPace<S, T> {
+    fn clone(&self) -> Self {
+        // a clone waits for its own ticks
+        PoissonPace {
+            inner: self.inner.clone(),
+            shared: Arc::clone(&self.shared),
+            waiting: None,
+            tick: None,
+        }
+    }
+}
+
+impl<S, T, Req> Service<Req> for PoissonPace<S, T>
+where
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    T: Timer + Unpin + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<S::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), BoxError>> {
+        if self.tick.is_none() {
+            let shared = Arc::clone(&self.shared);
+            let waiting = self.waiting.get_or_insert_with(|| {
+                Box::pin(async move {
+                    let mut ticker = shared.ticker.lock().await;
+                    (&mut *ticker).await.map(|_| Instant::now())
+                })
+            });
+            let tick = futures_util::ready!(waiting.as_mut().poll(cx));
+            self.waiting = None;
+            match tick {
+                Some(t) => self.tick = Some(t),
+                None => return Poll::Ready(Err(ScheduleExhausted.into())),
+            }
+        }
+
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let sent = self
+            .tick
+            .take()
+            .expect("poll_ready must return Ready(Ok) before call");
+        let id = self.shared.next_id.fetch_add(1, Ordering::AcqRel);
+        let latencies = Arc::clone(&self.shared.latencies);
+        let record = move |end, outcome| -> Result<()> {
+            latencies
+                .lock()
+                .map_err(|_| Error::LockPoisoned)?
+                .record_outcome(id, sent, end, outcome)
+        };
+        // in-flight requests stay dropped if the run is cut off
+        if let Err(e) = record(None, RequestOutcome::Dropped) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(id, err = %e, "Failed to record request");
+            let _ = &e;
+        }
+
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            let outcome = match res {
+                Ok(_) => RequestOutcome::Ok,
+                Err(_) => RequestOutcome::Error(0),
+            };
+            if let Err(e) = record(Some(Instant::now()), outcome) {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(id, err = %e, "Failed to record response");
+                let _ = &e;
+            }
+            res.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::DistributionType;
+    use std::convert::Infallible;
+    use tower::{ServiceBuilder, ServiceExt};
+
+    // one tick per millisecond
+    fn layer(ticks: usize) -> PoissonPaceLayer {
+        let schedule = RequestSchedule::new(ticks, 1000., DistributionType::Uniform).unwrap();
+        PoissonPaceLayer::new(schedule, Duration::from_secs(10))
+    }
+
+    async fn double(x: u32) -> std::result::Result<u32, Infallible> {
+        Ok(x * 2)
+    }
+
+    // not ready until `until`
+    struct SlowReady {
+        until: Instant,
+    }
+
+    impl Service<u32> for SlowReady {
+        type Response = u32;
+        type Error = Infallible;
+        type Future = futures_util::future::Ready<std::result::Result<u32, Infallible>>;
+
+        fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), Infallible>> {
+            if Instant::now() < self.until {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        fn call(&mut self, req: u32) -> Self::Future {
+            futures_util::future::ready(Ok(req))
+        }
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_schedule() {
+        let layer = layer(10);
+        let handle = layer.handle();
+        let svc = ServiceBuilder::new().layer(layer).service_fn(double);
+        let calls = futures_util::future::join_all((0..3).map(|_| {
+            let mut svc = svc.clone();
+            async move {
+                let mut calls = 0;
+                while let Ok(svc) = svc.ready().await {
+                    svc.call(1).await.unwrap();
+                    calls += 1;
+                }
+                calls
+            }
+        }))
+        .await;
+        assert_eq!(calls.iter().sum::<usize>(), 10);
+        let latencies = handle.latencies().unwrap();
+        assert_eq!(latencies.len(), 10);
+        assert!(latencies.iter().all(|(_, e)| e.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn exhausted_schedule_fails_ready() {
+        let mut svc = ServiceBuilder::new().layer(layer(1)).service_fn(double);
+        svc.ready().await.unwrap().call(1).await.unwrap();
+        let err = match svc.ready().await {
+            Err(e) => e,
+            Ok(_) => panic!("expected the schedule to be exhausted"),
+        };
+        assert!(err.downcast_ref::<ScheduleExhausted>().is_some());
+    }
+
+    #[tokio::test]
+    async fn failed_calls_are_errors() {
+        let layer = layer(4);
+        let handle = layer.handle();
+        let mut svc = ServiceBuilder::new()
+            .layer(layer)
+            .service_fn(|x: u32| async move {
+                if x % 2 == 0 {
+                    Ok(x)
+                } else {
+                    Err("odd")
+                }
+            });
+        for x in 0..4 {
+            let res = svc.ready().await.unwrap().call(x).await;
+            assert_eq!(res.is_ok(), x % 2 == 0);
+        }
+        let latencies = handle.latencies().unwrap();
+        assert_eq!(latencies.get(1).unwrap().outcome, RequestOutcome::Error(0));
+        assert!(latencies.get(1).unwrap().received.is_some());
+        assert_eq!(latencies.get(2).unwrap().outcome, RequestOutcome::Ok);
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_are_dropped() {
+        let layer = layer(1);
+        let handle = layer.handle();
+        let mut svc = ServiceBuilder::new()
+            .layer(layer)
+            .service_fn(|_: u32| std::future::pending::<std::result::Result<u32, Infallible>>());
+        let _in_flight = svc.ready().await.unwrap().call(1);
+        let latencies = handle.latencies().unwrap();
+        assert_eq!(latencies.get(0).unwrap().outcome, RequestOutcome::Dropped);
+        assert!(latencies.get(0).unwrap().received.is_none());
+    }
+
+    #[tokio::test]
+    async fn waiting_for_the_inner_service_counts_as_latency() {
+        let layer = layer(1);
+        let handle = layer.handle();
+        let until = Instant::now() + Duration::from_millis(20);
+        let mut svc = ServiceBuilder::new()
+            .layer(layer)
+            .service(SlowReady { until });
+        svc.ready().await.unwrap().call(1).await.unwrap();
+        let latencies = handle.latencies().unwrap();
+        let latency = latencies.get(0).unwrap().latency().unwrap();
+        // the tick fired after a millisecond, and the service was ready 20ms in
+        assert!(latency >= Duration::from_millis(15), "{:?}", latency);
+    }
+}
@@ -0,0 +1,204 @@
+//! Caps the number of requests in flight, so that an open-loop client cannot pile up unbounded
+//! outstanding requests when the server stalls.
+//!
+//! Arrivals beyond the cap either wait for a slot, with the wait recorded as the request's
+//! queueing delay, or are shed and recorded as [`RequestOutcome::Shed`](super::histogram::RequestOutcome::Shed).
+use super::error::{Error, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// What to do with an arrival when the limit is reached.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum OverloadPolicy {
+    // wait for an in-flight request to finish
+    Queue,
+    // don't send the request
+    Shed,
+}
+
+impl std::str::FromStr for OverloadPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<OverloadPolicy> {
+        Ok(match s {
+            "queue" | "Queue" => OverloadPolicy::Queue,
+            "shed" | "Shed" => OverloadPolicy::Shed,
+            x => {
+                return Err(Error::UnknownName {
+                    kind: "overload policy",
+                    name: x.to_string(),
+                })
+            }
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct InFlightLimit {
+    pub max_in_flight: usize,
+    pub policy: OverloadPolicy,
+}
+
+impl InFlightLimit {
+    pub fn new(max_in_flight: usize, policy: OverloadPolicy) -> Self {
+        InFlightLimit {
+            max_in_flight,
+            policy,
+        }
+    }
+}
+
+/// Whether an arrival may be sent.
+#[derive(Debug)]
+pub enum Admission {
+    /// Send the request, holding the permit until it finishes. `queue_delay` is how long the
+    /// arrival waited for the permit, or None if one was free.
+    Admitted {
+        permit: OwnedSemaphorePermit,
+        queue_delay: Option<Duration>,
+    },
+    /// The limit was reached and the policy is to shed.
+    Shed,
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightLimiter {
+    semaphore: Arc<Semaphore>,
+    limit: InFlightLimit,
+}
+
+impl InFlightLimiter {
+    pub fn new(limit: InFlightLimit) -> Result<Self> {
+        if limit.max_in_flight == 0 {
+            return Err(Error::InvalidArgument(
+                "In-flight limit must be at least 1".to_string(),
+            ));
+        }
+        Ok(InFlightLimiter {
+            semaphore: Arc::new(Semaphore::new(limit.max_in_flight)),
+            limit,
+        })
+    }
+
+    pub fn limit(&self) -> InFlightLimit {
+        self.limit
+    }
+
+    /// Number of requests currently holding a permit.
+    pub fn in_flight(&self) -> usize {
+        self.limit.max_in_flight - self.semaphore.available_permits()
+    }
+
+    /// Admits an arrival under the limit's policy. Queued arrivals are admitted in order.
+    pub async fn admit(&self) -> Admission {
+        let arrival = Instant::now();
+        if let Ok(permit) = Arc::clone(&self.semaphore).try_acquire_owned() {
+            return Admission::Admitted {
+                permit,
+                queue_delay: None,
+            };
+        }
+
+        match self.limit.policy {
+            OverloadPolicy::Shed => Admission::Shed,
+            OverloadPolicy::Queue => {
+                let permit = Arc::clone(&self.semaphore)
+                    .acquire_owned()
+                    .await
+                    .expect("In-flight semaphore is never closed");
+                Admission::Admitted {
+                    permit,
+                    queue_delay: Some(arrival.elapsed()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permit(admission: Admission) -> (OwnedSemaphorePermit, Option<Duration>) {
+        match admission {
+            Admission::Admitted {
+                permit,
+                queue_delay,
+            } => (permit, queue_delay),
+            Admission::Shed => panic!("expected the arrival to be admitted"),
+        }
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(
+            "queue".parse::<OverloadPolicy>().unwrap(),
+            OverloadPolicy::Queue
+        );
+        assert_eq!(
+            "Shed".parse::<OverloadPolicy>().unwrap(),
+            OverloadPolicy::Shed
+        );
+        assert!("drop".parse::<OverloadPolicy>().is_err());
+    }
+
+    #[test]
+    fn rejects_a_zero_limit() {
+        assert!(matches!(
+            InFlightLimiter::new(InFlightLimit::new(0, OverloadPolicy::Queue)),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sheds_beyond_the_limit() {
+        let limiter = InFlightLimiter::new(InFlightLimit::new(2, OverloadPolicy::Shed)).unwrap();
+        let (a, delay) = permit(limiter.admit().await);
+        assert_eq!(delay, None);
+        let _b = permit(limiter.admit().await);
+        assert_eq!(limiter.in_flight(), 2);
+        assert!(matches!(limiter.admit().await, Admission::Shed));
+
+        // a finished request frees its slot
+        drop(a);
+        assert_eq!(limiter.in_flight(), 1);
+        assert_eq!(permit(limiter.admit().await).1, None);
+    }
+
+    #[tokio::test]
+    async fn queued_arrivals_record_their_wait() {
+        let limiter = InFlightLimiter::new(InFlightLimit::new(1, OverloadPolicy::Queue)).unwrap();
+        let (held, _) = permit(limiter.admit().await);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(held);
+        });
+        let (_permit, delay) = permit(limiter.admit().await);
+        let delay = delay.expect("the arrival should have queued");
+        assert!(delay >= Duration::from_millis(20), "{:?}", delay);
+        assert_eq!(limiter.in_flight(), 1);
+    }
+
+    #[tokio::test]
+    async fn queued_arrivals_are_admitted_in_order() {
+        let limiter = InFlightLimiter::new(InFlightLimit::new(1, OverloadPolicy::Queue)).unwrap();
+        let (held, _) = permit(limiter.admit().await);
+        let order = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut waiters = vec![];
+        for i in 0..3 {
+            let limiter = limiter.clone();
+            let order = Arc::clone(&order);
+            waiters.push(tokio::spawn(async move {
+                let _permit = permit(limiter.admit().await);
+                order.lock().unwrap().push(i);
+            }));
+            // let each waiter join the queue before the next
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        for w in waiters {
+            w.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2]);
+    }
+}
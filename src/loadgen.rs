@@ -0,0 +1,359 @@
+//! An open-loop load generator for services that echo requests back.
+//!
+//! Requests go over a [`Transport`], which handles embedding each request's ID and matching
+//! responses back to it; see [`transport`](super::transport) for the wire formats.
+//! [`EchoServer`] returns requests unchanged over UDP or TCP, and is useful for testing the
+//! generator itself.
+use super::error::{Error, Result};
+use super::histogram::{LatencyMap, RequestOutcome};
+use super::recorder::ConcurrentLatencyRecorder;
+use super::requests::RequestSchedule;
+use super::server::accept_loop;
+use super::summary_stats::SummaryStats;
+use super::transport::{RequestSender, ResponseReceiver, Transport, MAX_DATAGRAM};
+use super::SpinTicker;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Protocol> {
+        Ok(match s {
+            "udp" | "UDP" => Protocol::Udp,
+            "tcp" | "TCP" => Protocol::Tcp,
+            x => {
+                return Err(Error::UnknownName {
+                    kind: "protocol",
+                    name: x.to_string(),
+                })
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadGenConfig {
+    // requests are spread round-robin over this many connections
+    pub connections: usize,
+    // stop sending after this long, even if the schedule has requests left
+    pub duration: Duration,
+    // how long to wait for outstanding responses after the last send;
+    // requests still outstanding afterwards are counted as dropped
+    pub drain: Duration,
+    pub timeout: Option<Duration>,
+    pub warmup: Duration,
+    pub cooldown: Duration,
+    pub histogram_precision: Option<u64>,
+}
+
+impl Default for LoadGenConfig {
+    fn default() -> Self {
+        LoadGenConfig {
+            connections: 1,
+            duration: Duration::from_secs(10),
+            drain: Duration::from_secs(1),
+            timeout: None,
+            warmup: Duration::from_secs(1),
+            cooldown: Duration::from_secs(1),
+            histogram_precision: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadGenResult {
+    pub latencies: LatencyMap,
+    pub stats: SummaryStats,
+}
+
+/// A payload of `size` zero bytes for every request.
+pub fn fixed_payload(size: usize) -> impl FnMut(usize) -> Vec<u8> {
+    move |_| vec![0u8; size]
+}
+
+async fn receive<R: ResponseReceiver>(mut r: R, recorder: Arc<ConcurrentLatencyRecorder>) {
+    loop {
+        match r.recv().await {
+            Ok(id) if !recorder.is_finished(id) => {
+                if let Err(e) = recorder.record_received(id) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(err = %e, "Ignoring response");
+                    let _ = &e;
+                }
+            }
+            Ok(id) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(id, "Ignoring duplicate response");
+                let _ = &id;
+            }
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(err = %e, "Connection closed");
+                let _ = &e;
+                return;
+            }
+        }
+    }
+}
+
+/// Sends one request per tick of a [`SpinTicker`] over `schedule` on connections opened by
+/// `transport`, and records the responses.
+///
+/// `payload` is called with each request ID to produce the request body.
+pub async fn run_load<T, P>(
+    transport: &T,
+    config: &LoadGenConfig,
+    schedule: RequestSchedule,
+    mut payload: P,
+) -> Result<LoadGenResult>
+where
+    T: Transport,
+    P: FnMut(usize) -> Vec<u8>,
+{
+    if config.connections == 0 {
+        return Err(Error::InvalidArgument(
+            "Load generator needs at least one connection".to_string(),
+        ));
+    }
+
+    let mut recorder = ConcurrentLatencyRecorder::from_schedule(&schedule);
+    if let Some(timeout) = config.timeout {
+        recorder = recorder.with_timeout(timeout);
+    }
+    let recorder = Arc::new(recorder);
+    let mut senders = Vec::with_capacity(config.connections);
+    let mut receivers = Vec::with_capacity(config.connections);
+    for _ in 0..config.connections {
+        let (s, r) = transport.connect().await?;
+        senders.push(s);
+        receivers.push(tokio::spawn(receive(r, Arc::clone(&recorder))));
+    }
+
+    let mut ticker = SpinTicker::new(schedule, config.duration);
+    let mut sent = 0;
+    while (&mut ticker).await.is_some() {
+        let id = sent;
+        sent += 1;
+        let body = payload(id);
+
+        let conn = id % senders.len();
+        recorder.record_sent(id)?;
+        if let Err(e) = senders[conn].send(id, &body).await {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(id, err = %e, "Send failed");
+            let _ = &e;
+            recorder.record_outcome(id, RequestOutcome::Dropped)?;
+        }
+    }
+    let drained = tokio::time::timeout(config.drain, async {
+        let mut next = 0;
+        while next < sent {
+            if recorder.is_finished(next) {
+                next += 1;
+            } else {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+    })
+    .await;
+    if drained.is_err() {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(sent, "Requests still outstanding after drain period");
+    }
+    for r in receivers {
+        r.abort();
+    }
+
+    let latencies = recorder.to_latency_map()?;
+    let stats = SummaryStats::new(
+        &latencies,
+        config.warmup,
+        config.cooldown,
+        true,
+        config.histogram_precision,
+    )?;
+    Ok(LoadGenResult { latencies, stats })
+}
+
+/// Echoes every UDP datagram, or every byte of every TCP connection, back to its sender.
+///
+/// The server runs on the tokio runtime until it is dropped.
+pub struct EchoServer {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl EchoServer {
+    /// Binds to `addr`; use port 0 to pick a free port.
+    pub async fn bind(protocol: Protocol, addr: SocketAddr) -> Result<Self> {
+        let (local_addr, task) = match protocol {
+            Protocol::Udp => {
+                let sk = UdpSocket::bind(addr).await.map_err(|source| Error::Bind {
+                    addr: addr.to_string(),
+                    source,
+                })?;
+                (sk.local_addr()?, tokio::spawn(echo_udp(sk)))
+            }
+            Protocol::Tcp => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .map_err(|source| Error::Bind {
+                        addr: addr.to_string(),
+                        source,
+                    })?;
+                (listener.local_addr()?, tokio::spawn(echo_tcp(listener)))
+            }
+        };
+        Ok(EchoServer { local_addr, task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn echo_udp(sk: UdpSocket) {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        match sk.recv_from(&mut buf).await {
+            Ok((len, from)) => {
+                if let Err(e) = sk.send_to(&buf[..len], from).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(err = %e, ?from, "Echo send failed");
+                    let _ = &e;
+                }
+            }
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(err = %e, "Echo receive failed");
+                let _ = &e;
+            }
+        }
+    }
+}
+
+async fn echo_tcp(listener: TcpListener) {
+    accept_loop(listener, |stream, from| async move {
+        let _ = &from;
+        let (mut r, mut w) = stream.into_split();
+        if let Err(e) = tokio::io::copy(&mut r, &mut w).await {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(err = %e, ?from, "Echo connection failed");
+            let _ = &e;
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::DistributionType;
+    use crate::transport::{channel_echo, ChannelTransport, TcpTransport, UdpTransport};
+
+    fn config(connections: usize) -> LoadGenConfig {
+        LoadGenConfig {
+            connections,
+            duration: Duration::from_millis(200),
+            drain: Duration::from_millis(200),
+            warmup: Duration::from_secs(0),
+            cooldown: Duration::from_secs(0),
+            ..Default::default()
+        }
+    }
+
+    fn schedule() -> RequestSchedule {
+        RequestSchedule::new_seeded(100, 1000.0, DistributionType::Uniform, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_protocols() {
+        assert_eq!("udp".parse::<Protocol>().unwrap(), Protocol::Udp);
+        assert_eq!("TCP".parse::<Protocol>().unwrap(), Protocol::Tcp);
+        assert!("quic".parse::<Protocol>().is_err());
+    }
+
+    #[tokio::test]
+    async fn records_every_response() {
+        let result = run_load(&channel_echo(), &config(3), schedule(), fixed_payload(16))
+            .await
+            .unwrap();
+        assert_eq!(result.latencies.len(), 100);
+        assert_eq!(result.stats.outcomes.ok, 100);
+        assert_eq!(result.stats.outcomes.dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_are_dropped() {
+        let transport = ChannelTransport::new(|id, _| async move { id % 2 == 0 });
+        let result = run_load(&transport, &config(1), schedule(), fixed_payload(0))
+            .await
+            .unwrap();
+        assert_eq!(result.stats.outcomes.ok, 50);
+        assert_eq!(result.stats.outcomes.dropped, 50);
+        assert!(result.latencies.get(1).unwrap().latency().is_none());
+    }
+
+    #[tokio::test]
+    async fn payload_gets_each_id() {
+        let transport = ChannelTransport::new(|id, payload: Vec<u8>| async move {
+            payload == (id as u64).to_le_bytes()
+        });
+        let payload = |id: usize| (id as u64).to_le_bytes().to_vec();
+        let result = run_load(&transport, &config(2), schedule(), payload)
+            .await
+            .unwrap();
+        assert_eq!(result.stats.outcomes.ok, 100);
+    }
+
+    #[tokio::test]
+    async fn needs_a_connection() {
+        assert!(matches!(
+            run_load(&channel_echo(), &config(0), schedule(), fixed_payload(0)).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn echo_server_over_udp_and_tcp() {
+        let localhost: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+        let udp = EchoServer::bind(Protocol::Udp, localhost).await.unwrap();
+        let result = run_load(
+            &UdpTransport::new(udp.local_addr()),
+            &config(2),
+            schedule(),
+            fixed_payload(32),
+        )
+        .await
+        .unwrap();
+        // loopback UDP rarely drops, but it may
+        assert!(result.stats.outcomes.ok > 90, "{:?}", result.stats.outcomes);
+
+        let tcp = EchoServer::bind(Protocol::Tcp, localhost).await.unwrap();
+        let result = run_load(
+            &TcpTransport::new(tcp.local_addr()),
+            &config(2),
+            schedule(),
+            fixed_payload(32),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.stats.outcomes.ok, 100);
+    }
+}
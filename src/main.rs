@@ -0,0 +1,443 @@
+//! Command-line analysis of latency logs and summaries.
+use color_eyre::eyre::{bail, Result, WrapErr};
+use poisson_ticker::compare::{compare_stats, CompareConfig, DistributionTest, Verdict};
+use poisson_ticker::coordinator::{Coordinator, CoordinatorConfig};
+use poisson_ticker::histogram::LatencyMap;
+use poisson_ticker::report::ReportFormat;
+use poisson_ticker::requests::{self, DistributionType, RequestClassMix, RequestSchedule};
+use poisson_ticker::summary_stats::{self, timeline, SummaryStats};
+use poisson_ticker::timestamps::TimestampLog;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "poisson-ticker", about = "Analyze latency logs and summaries.")]
+enum Command {
+    /// Compute summary statistics from a latency log or timestamp file.
+    Summarize {
+        #[structopt(flatten)]
+        input: LogInput,
+        #[structopt(flatten)]
+        window: WindowOpts,
+        /// Output format: markdown, json, jsonl, csv, or prometheus.
+        #[structopt(long, default_value = "markdown")]
+        format: ReportFormat,
+        /// Write to this file instead of stdout.
+        #[structopt(short, long)]
+        out: Option<String>,
+    },
+    /// Compare the latency distributions of two summary JSON files.
+    Compare {
+        baseline: String,
+        candidate: String,
+        #[structopt(long, use_delimiter = true, default_value = "0.5,0.9,0.99,0.999")]
+        quantiles: Vec<f64>,
+        /// Significance level.
+        #[structopt(long, default_value = "0.05")]
+        alpha: f64,
+        /// Distribution test: ks or mw.
+        #[structopt(long, default_value = "ks")]
+        test: DistributionTest,
+        #[structopt(long, default_value = "1000")]
+        bootstrap_iterations: usize,
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+        /// Print the comparison as JSON.
+        #[structopt(long)]
+        json: bool,
+        /// Exit with status 1 if the candidate regressed.
+        #[structopt(long)]
+        fail_on_regression: bool,
+    },
+    /// Merge summary JSON files from runs on several machines into one summary.
+    Merge {
+        #[structopt(required = true, min_values = 2)]
+        inputs: Vec<String>,
+        /// Write to this file instead of stdout.
+        #[structopt(short, long)]
+        out: Option<String>,
+    },
+    /// Print throughput and latency over consecutive windows of a run.
+    Timeline {
+        #[structopt(flatten)]
+        input: LogInput,
+        /// Window length, in milliseconds.
+        #[structopt(long, default_value = "1000")]
+        window_ms: u64,
+        #[structopt(long, use_delimiter = true, default_value = "0.5,0.99")]
+        quantiles: Vec<f64>,
+        /// Histogram bucket width, in nanoseconds.
+        #[structopt(long)]
+        precision_ns: Option<u64>,
+    },
+    /// Generate or inspect request schedules.
+    Schedule(ScheduleCommand),
+    /// Coordinate an experiment across client processes and write the merged summary.
+    Coordinate {
+        /// Address clients connect to.
+        #[structopt(long, default_value = "0.0.0.0:7000")]
+        listen: SocketAddr,
+        /// Number of clients to wait for.
+        #[structopt(long)]
+        clients: usize,
+        /// Requests per second, summed over all clients.
+        #[structopt(long)]
+        rate: f64,
+        /// Interarrival distribution: exponential or uniform.
+        #[structopt(long, default_value = "exponential")]
+        distribution: DistributionType,
+        #[structopt(long, default_value = "10")]
+        duration_s: f64,
+        /// Client i generates its schedule with this seed plus i.
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+        /// Write to this file instead of stdout.
+        #[structopt(short, long)]
+        out: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ScheduleCommand {
+    /// Generate a schedule, write it to a file, and print an inspection report.
+    Generate {
+        /// Interarrival distribution: exponential or uniform.
+        #[structopt(long, default_value = "exponential")]
+        distribution: DistributionType,
+        /// Requests per second.
+        #[structopt(long)]
+        rate: f64,
+        /// Number of requests.
+        #[structopt(long, conflicts_with = "duration-s", required_unless = "duration-s")]
+        count: Option<usize>,
+        /// Generate enough requests to last this long at the given rate.
+        #[structopt(long)]
+        duration_s: Option<f64>,
+        /// Seed for interarrivals and class assignment. A random seed is printed if unset.
+        #[structopt(long)]
+        seed: Option<u64>,
+        /// Request class mix, e.g. `get:0.9,put:0.1`.
+        #[structopt(long)]
+        mix: Option<RequestClassMix>,
+        #[structopt(short, long)]
+        out: String,
+        #[structopt(flatten)]
+        report: ScheduleReportOpts,
+    },
+    /// Print an inspection report for a schedule file.
+    Inspect {
+        path: String,
+        #[structopt(flatten)]
+        report: ScheduleReportOpts,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct ScheduleReportOpts {
+    /// Distribution to test the interarrivals against.
+    #[structopt(long = "fit", default_value = "exponential")]
+    fit: DistributionType,
+    /// Print the report as JSON.
+    #[structopt(long)]
+    json: bool,
+}
+
+impl ScheduleReportOpts {
+    fn print(&self, schedule: &RequestSchedule) -> Result<()> {
+        let report = schedule.inspect(self.fit)?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print!("{}", report);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct LogInput {
+    /// A log written by `LatencyMap::log_to_file`, or a timestamp file in either format.
+    path: String,
+    /// Latency logs have no send times, so requests are assumed to be sent this far apart.
+    #[structopt(long, default_value = "1000")]
+    interarrival_us: u64,
+}
+
+impl LogInput {
+    fn load(&self) -> Result<LatencyMap> {
+        let mut prefix = [0u8; 20];
+        let n = File::open(&self.path)
+            .wrap_err_with(|| format!("Failed to open {}", self.path))?
+            .read(&mut prefix)?;
+        let prefix = &prefix[..n];
+        if prefix.starts_with(b"PTTS") || prefix.starts_with(b"# anchor_unix_nanos=") {
+            let mut file = File::open(&self.path)?;
+            Ok(TimestampLog::read(&mut file)
+                .wrap_err_with(|| format!("Failed to read timestamps from {}", self.path))?
+                .to_latency_map()?)
+        } else {
+            Ok(LatencyMap::from_log_file(
+                &self.path,
+                Duration::from_micros(self.interarrival_us),
+            )?)
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct WindowOpts {
+    /// Exclude requests sent within this long of the first send.
+    #[structopt(long, default_value = "0")]
+    warmup_ms: u64,
+    /// Exclude requests sent within this long of the last send.
+    #[structopt(long, default_value = "0")]
+    cooldown_ms: u64,
+    /// Count responses received after the window closes.
+    #[structopt(long)]
+    no_time_window: bool,
+    /// Histogram bucket width, in nanoseconds.
+    #[structopt(long)]
+    precision_ns: Option<u64>,
+}
+
+fn output(out: &Option<String>) -> Result<Box<dyn Write>> {
+    Ok(match out {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).wrap_err_with(|| format!("Failed to create {}", path))?,
+        )),
+        None => Box::new(std::io::stdout()),
+    })
+}
+
+// a zero bucket width would divide by zero when bucketing latencies
+fn check_precision(precision_ns: Option<u64>) -> Result<()> {
+    if precision_ns == Some(0) {
+        bail!("Precision must be positive: 0");
+    }
+    Ok(())
+}
+
+fn micros(nanos: f64) -> f64 {
+    nanos / 1e3
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    match Command::from_args() {
+        Command::Summarize {
+            input,
+            window,
+            format,
+            out,
+        } => {
+            check_precision(window.precision_ns)?;
+            let map = input.load()?;
+            let stats = SummaryStats::new(
+                &map,
+                Duration::from_millis(window.warmup_ms),
+                Duration::from_millis(window.cooldown_ms),
+                !window.no_time_window,
+                window.precision_ns,
+            )?;
+            let mut w = output(&out)?;
+            format.reporter().write_summary(&stats, &mut w)?;
+            w.flush()?;
+        }
+        Command::Compare {
+            baseline,
+            candidate,
+            quantiles,
+            alpha,
+            test,
+            bootstrap_iterations,
+            seed,
+            json,
+            fail_on_regression,
+        } => {
+            let config = CompareConfig {
+                quantiles,
+                alpha,
+                bootstrap_iterations,
+                test,
+                seed,
+            };
+            let comparison = compare_stats(
+                &summary_stats::read_from_file(&baseline)?,
+                &summary_stats::read_from_file(&candidate)?,
+                &config,
+            )?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&comparison)?);
+            } else {
+                println!(
+                    "| quantile | baseline (µs) | candidate (µs) | diff (µs) | {}% CI (µs) | significant |",
+                    (1.0 - alpha) * 100.0
+                );
+                println!("|---|---|---|---|---|---|");
+                for q in comparison.quantiles.iter() {
+                    println!(
+                        "| p{} | {:.1} | {:.1} | {:+.1} | [{:+.1}, {:+.1}] | {} |",
+                        q.quantile * 100.0,
+                        micros(q.baseline as f64),
+                        micros(q.candidate as f64),
+                        micros(q.difference as f64),
+                        micros(q.ci_low),
+                        micros(q.ci_high),
+                        if q.significant { "yes" } else { "no" }
+                    );
+                }
+                println!();
+                println!(
+                    "{:?}: statistic = {:.4}, p = {:.4}",
+                    comparison.test.test, comparison.test.statistic, comparison.test.p_value
+                );
+                println!("verdict: {:?}", comparison.verdict);
+            }
+
+            if fail_on_regression && comparison.verdict == Verdict::Regressed {
+                std::process::exit(1);
+            }
+        }
+        Command::Merge { inputs, out } => {
+            let stats = inputs
+                .iter()
+                .map(|p| summary_stats::read_from_file(p))
+                .collect::<Result<Vec<_>, _>>()?;
+            let merged = SummaryStats::merge(&stats)?;
+            let mut w = output(&out)?;
+            serde_json::to_writer(&mut w, &merged)?;
+            writeln!(w)?;
+            w.flush()?;
+        }
+        Command::Timeline {
+            input,
+            window_ms,
+            quantiles,
+            precision_ns,
+        } => {
+            if let Some(q) = quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+                bail!("Quantiles must be in [0, 1]: {}", q);
+            }
+            if window_ms == 0 {
+                bail!("Window must be positive: 0");
+            }
+            check_precision(precision_ns)?;
+            let map = input.load()?;
+            let windows = timeline(&map, Duration::from_millis(window_ms), precision_ns)?;
+
+            print!("| start (s) | offered (req/s) | throughput (req/s) | errors | dropped |");
+            for q in quantiles.iter() {
+                print!(" p{} (µs) |", q * 100.0);
+            }
+            println!();
+            println!("|{}", "---|".repeat(5 + quantiles.len()));
+            for w in windows.iter() {
+                print!(
+                    "| {:.3} | {:.1} | {:.1} | {} | {} |",
+                    w.start,
+                    w.offered_load(),
+                    w.throughput(),
+                    w.outcomes.total_errors() + w.outcomes.timeout,
+                    w.outcomes.dropped
+                );
+                for q in quantiles.iter() {
+                    match w.histogram.count {
+                        0 => print!(" - |"),
+                        _ => print!(
+                            " {:.1} |",
+                            micros(w.histogram.value_at_quantile(*q)? as f64)
+                        ),
+                    }
+                }
+                println!();
+            }
+        }
+        Command::Schedule(ScheduleCommand::Generate {
+            distribution,
+            rate,
+            count,
+            duration_s,
+            seed,
+            mix,
+            out,
+            report,
+        }) => {
+            if !rate.is_finite() || rate <= 0.0 {
+                bail!("Rate must be positive: {}", rate);
+            }
+            let count = match (count, duration_s) {
+                (Some(c), _) => c,
+                (None, Some(d)) if d.is_finite() && d > 0.0 => (d * rate).ceil() as usize,
+                (None, Some(d)) => bail!("Duration must be positive: {}", d),
+                (None, None) => bail!("One of --count or --duration-s is required"),
+            };
+            let seed = seed.unwrap_or_else(|| {
+                let seed = rand::random();
+                eprintln!("seed: {}", seed);
+                seed
+            });
+
+            let schedule = match mix {
+                Some(mix) => {
+                    RequestSchedule::new_with_classes(count, rate, distribution, &mix, seed)?
+                }
+                None => RequestSchedule::new_seeded(count, rate, distribution, seed)?,
+            };
+            requests::write_to_file(&schedule, out.clone())
+                .wrap_err_with(|| format!("Failed to write schedule to {}", out))?;
+            report.print(&schedule)?;
+        }
+        Command::Schedule(ScheduleCommand::Inspect { path, report }) => {
+            report.print(&requests::read_from_file(&path)?)?;
+        }
+        Command::Coordinate {
+            listen,
+            clients,
+            rate,
+            distribution,
+            duration_s,
+            seed,
+            out,
+        } => {
+            if clients == 0 {
+                bail!("Experiment needs at least one client");
+            }
+            if !rate.is_finite() || rate <= 0.0 {
+                bail!("Rate must be positive: {}", rate);
+            }
+            if !duration_s.is_finite() || duration_s <= 0.0 {
+                bail!("Duration must be positive: {}", duration_s);
+            }
+            let config = CoordinatorConfig {
+                clients,
+                total_rate: rate,
+                distribution,
+                duration: Duration::from_secs_f64(duration_s),
+                seed,
+                ..Default::default()
+            };
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let result = rt.block_on(async {
+                let coordinator = Coordinator::bind(listen, config).await?;
+                eprintln!(
+                    "waiting for {} clients on {}",
+                    clients,
+                    coordinator.local_addr()?
+                );
+                coordinator.run().await
+            })?;
+            let mut w = output(&out)?;
+            serde_json::to_writer(&mut w, &result.stats)?;
+            writeln!(w)?;
+            w.flush()?;
+        }
+    }
+
+    Ok(())
+}
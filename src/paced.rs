@@ -0,0 +1,252 @@
+//! Releasing the items of any [`Stream`] on the ticks of a [`SpinTicker`].
+//!
+//! The inner stream and the ticker are polled together: an item is released once it is ready
+//! and its tick has fired. The next interarrival starts when the previous item is released, so
+//! a slow inner stream pushes back every later item; [`Paced::with_timing`] shows how often
+//! that happens.
+//!
+//! # Example
+//! ```rust
+//! # #[tokio::main]
+//! # async fn main() {
+//! use futures_util::stream::{self, StreamExt};
+//! use poisson_ticker::paced::PacedStreamExt;
+//! use poisson_ticker::requests::{DistributionType, RequestSchedule};
+//!
+//! let schedule = RequestSchedule::new(10, 1000., DistributionType::Exponential).unwrap();
+//! let mut paced = stream::iter(0..100).poisson_paced(schedule).with_timing();
+//! let mut released = 0;
+//! while let Some((_item, timing)) = paced.next().await {
+//!     // the items are all ready immediately, so only the ticker holds them back
+//!     assert_eq!(timing.stream_wait, std::time::Duration::from_secs(0));
+//!     released += 1;
+//! }
+//! // the schedule runs out first
+//! assert_eq!(released, 10);
+//! # }
+//! ```
+use super::requests::RequestSchedule;
+use super::{SpinTicker, SpinTimer, Timer};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+/// How long one released item was held up, and by what.
+///
+/// At most one of the two is nonzero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PaceTiming {
+    // the tick fired, but the inner stream had no item ready yet
+    pub stream_wait: Duration,
+    // the item was ready, but its tick had not fired yet
+    pub ticker_wait: Duration,
+}
+
+pub trait PacedStreamExt: Stream + Sized {
+    /// Releases items at the interarrivals of `schedule`, ending when either the stream or the
+    /// schedule does.
+    fn poisson_paced(self, schedule: RequestSchedule) -> Paced<Self, SpinTimer> {
+        // a duration that can't elapse, so only the schedule's length bounds the ticker
+        self.paced_by(SpinTicker::new(schedule, Duration::MAX))
+    }
+
+    /// Releases one item per tick of `ticker`.
+    fn paced_by<T: Timer + Unpin>(self, ticker: SpinTicker<T>) -> Paced<Self, T> {
+        Paced {
+            inner: self,
+            ticker,
+            ticked_at: None,
+            ready: None,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream> PacedStreamExt for S {}
+
+/// Stream returned by [`PacedStreamExt::poisson_paced`].
+///
+/// The inner stream must be `Unpin`; wrap it in `Box::pin` if it isn't.
+pub struct Paced<S: Stream, T> {
+    inner: S,
+    ticker: SpinTicker<T>,
+    // when the tick for the next item fired
+    ticked_at: Option<Instant>,
+    // the next item and when the inner stream produced it
+    ready: Option<(S::Item, Instant)>,
+    done: bool,
+}
+
+// Buffered items are never pinned.
+impl<S: Stream + Unpin, T: Unpin> Unpin for Paced<S, T> {}
+
+impl<S: Stream, T> Paced<S, T> {
+    /// Yields each item along with how long it was held up by the stream or the ticker.
+    pub fn with_timing(self) -> Timed<S, T> {
+        Timed(self)
+    }
+}
+
+impl<S, T> Paced<S, T>
+where
+    S: Stream + Unpin,
+    T: Timer + Unpin,
+{
+    fn poll_timed(&mut self, cx: &mut Context) -> Poll<Option<(S::Item, PaceTiming)>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        // both sides share one timestamp, so readiness within the same poll is no wait at all
+        let now = Instant::now();
+        if self.ticked_at.is_none() {
+            match Pin::new(&mut self.ticker).poll_next(cx) {
+                Poll::Ready(Some(())) => self.ticked_at = Some(now),
+                Poll::Ready(None) => {
+                    self.done = true;
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => (),
+            }
+        }
+        if self.ready.is_none() {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(item)) => self.ready = Some((item, now)),
+                Poll::Ready(None) => {
+                    self.done = true;
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => (),
+            }
+        }
+
+        match (self.ticked_at, self.ready.take()) {
+            (Some(ticked_at), Some((item, ready_at))) => {
+                self.ticked_at = None;
+                let timing = PaceTiming {
+                    stream_wait: ready_at.saturating_duration_since(ticked_at),
+                    ticker_wait: ticked_at.saturating_duration_since(ready_at),
+                };
+                Poll::Ready(Some((item, timing)))
+            }
+            (_, ready) => {
+                self.ready = ready;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S, T> Stream for Paced<S, T>
+where
+    S: Stream + Unpin,
+    T: Timer + Unpin,
+{
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        self.get_mut()
+            .poll_timed(cx)
+            .map(|next| next.map(|(item, _)| item))
+    }
+}
+
+/// Stream returned by [`Paced::with_timing`].
+pub struct Timed<S: Stream, T>(Paced<S, T>);
+
+impl<S, T> Stream for Timed<S, T>
+where
+    S: Stream + Unpin,
+    T: Timer + Unpin,
+{
+    type Item = (S::Item, PaceTiming);
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.get_mut().0.poll_timed(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::DistributionType;
+    use futures_util::stream::{self, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // one tick per millisecond
+    fn schedule(ticks: usize) -> RequestSchedule {
+        RequestSchedule::new(ticks, 1000., DistributionType::Uniform).unwrap()
+    }
+
+    fn check_timing(timing: &PaceTiming) {
+        let zero = Duration::from_secs(0);
+        assert!(
+            timing.stream_wait == zero || timing.ticker_wait == zero,
+            "{:?}",
+            timing
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_stream_pushes_back_later_ticks() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let items = stream::poll_fn(move |cx| rx.poll_recv(cx));
+        tokio::spawn(async move {
+            tx.send(0).unwrap();
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            tx.send(1).unwrap();
+            tx.send(2).unwrap();
+        });
+
+        // ticks every 50ms
+        let schedule = RequestSchedule::new(3, 20., DistributionType::Uniform).unwrap();
+        let mut paced = items.poisson_paced(schedule).with_timing();
+        let (item, timing) = paced.next().await.unwrap();
+        assert_eq!(item, 0);
+        check_timing(&timing);
+
+        // the tick fired long before the item arrived
+        let (item, timing) = paced.next().await.unwrap();
+        let released = Instant::now();
+        assert_eq!(item, 1);
+        assert!(timing.stream_wait > Duration::from_secs(0), "{:?}", timing);
+        check_timing(&timing);
+
+        // the next interarrival only starts once the late item is released
+        let (item, timing) = paced.next().await.unwrap();
+        assert_eq!(item, 2);
+        assert!(released.elapsed() >= Duration::from_millis(25));
+        assert!(timing.ticker_wait > Duration::from_secs(0), "{:?}", timing);
+        check_timing(&timing);
+
+        assert!(paced.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn ends_with_the_schedule() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&pulled);
+        let items = stream::iter(0..10).inspect(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let mut paced = items.poisson_paced(schedule(3)).with_timing();
+        let mut released = vec![];
+        while let Some((item, timing)) = paced.next().await {
+            check_timing(&timing);
+            released.push(item);
+        }
+        assert_eq!(released, [0, 1, 2]);
+        // once done, neither side is polled again
+        assert!(paced.next().await.is_none());
+        assert_eq!(pulled.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn ends_with_the_stream() {
+        let mut paced = stream::iter(0..2).poisson_paced(schedule(10));
+        assert_eq!(paced.next().await, Some(0));
+        assert_eq!(paced.next().await, Some(1));
+        assert_eq!(paced.next().await, None);
+        assert_eq!(paced.next().await, None);
+    }
+}
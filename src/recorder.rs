@@ -0,0 +1,451 @@
+use super::error::{Error, Result};
+use super::histogram::{LatencyMap, RequestOutcome};
+use super::requests::RequestSchedule;
+use super::summary_stats::SummaryStats;
+use super::timestamps::RunAnchor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Outcomes are packed into a u64 so they can be stored atomically.
+// 0 means no outcome was recorded; errors carry their code in the low 32 bits.
+const OUTCOME_NONE: u64 = 0;
+const OUTCOME_OK: u64 = 1;
+const OUTCOME_TIMEOUT: u64 = 2;
+const OUTCOME_DROPPED: u64 = 3;
+const OUTCOME_RETRIED: u64 = 4;
+const OUTCOME_SHED: u64 = 5;
+const OUTCOME_ERROR: u64 = 1 << 32;
+
+fn encode_outcome(outcome: RequestOutcome) -> u64 {
+    match outcome {
+        RequestOutcome::Ok => OUTCOME_OK,
+        RequestOutcome::Timeout => OUTCOME_TIMEOUT,
+        RequestOutcome::Dropped => OUTCOME_DROPPED,
+        RequestOutcome::Retried => OUTCOME_RETRIED,
+        RequestOutcome::Shed => OUTCOME_SHED,
+        RequestOutcome::Error(code) => OUTCOME_ERROR | code as u64,
+    }
+}
+
+fn decode_outcome(encoded: u64) -> Option<RequestOutcome> {
+    match encoded {
+        OUTCOME_NONE => None,
+        OUTCOME_OK => Some(RequestOutcome::Ok),
+        OUTCOME_TIMEOUT => Some(RequestOutcome::Timeout),
+        OUTCOME_DROPPED => Some(RequestOutcome::Dropped),
+        OUTCOME_RETRIED => Some(RequestOutcome::Retried),
+        OUTCOME_SHED => Some(RequestOutcome::Shed),
+        e => Some(RequestOutcome::Error(e as u32)),
+    }
+}
+
+/// Records send and receive times from many tasks at once, without locks.
+///
+/// Slots are preallocated and indexed by request ID, so IDs must be less than the capacity.
+/// Once the run finishes, convert it into a [`LatencyMap`] or [`SummaryStats`].
+///
+/// # Example
+/// ```rust
+/// # use poisson_ticker::recorder::ConcurrentLatencyRecorder;
+/// # use std::sync::Arc;
+/// let recorder = Arc::new(ConcurrentLatencyRecorder::new(2));
+/// recorder.record_sent(0).unwrap();
+/// recorder.record_received(0).unwrap();
+/// recorder.record_sent(1).unwrap();
+/// let map = recorder.to_latency_map().unwrap();
+/// assert_eq!(map.len(), 2);
+/// assert!(map.get(0).unwrap().is_ok());
+/// assert!(map.get(1).unwrap().received.is_none());
+/// ```
+#[derive(Debug)]
+pub struct ConcurrentLatencyRecorder {
+    // all times are stored as nanoseconds since the anchor, plus one; 0 means not recorded
+    anchor: RunAnchor,
+    sent: Vec<AtomicU64>,
+    received: Vec<AtomicU64>,
+    outcomes: Vec<AtomicU64>,
+    // nanoseconds spent waiting for an in-flight limit, plus one; 0 means not queued
+    queue_delays: Vec<AtomicU64>,
+    // request class for each slot
+    classes: Vec<usize>,
+    timeout: Option<Duration>,
+}
+
+impl ConcurrentLatencyRecorder {
+    pub fn new(capacity: usize) -> Self {
+        Self::new_with_run_anchor(capacity, RunAnchor::now())
+    }
+
+    /// Creates a recorder whose times are stored relative to `anchor`.
+    ///
+    /// No time recorded may be earlier than the anchor.
+    pub fn new_with_anchor(capacity: usize, anchor: Instant) -> Self {
+        Self::new_with_run_anchor(capacity, RunAnchor::from_instant(anchor))
+    }
+
+    /// Creates a recorder whose times are stored relative to `anchor`, which is also recorded in
+    /// the resulting [`LatencyMap`] for persisting absolute timestamps.
+    pub fn new_with_run_anchor(capacity: usize, anchor: RunAnchor) -> Self {
+        ConcurrentLatencyRecorder {
+            anchor,
+            sent: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+            received: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+            outcomes: (0..capacity)
+                .map(|_| AtomicU64::new(OUTCOME_NONE))
+                .collect(),
+            queue_delays: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+            classes: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    /// Creates a recorder with one slot per request in the schedule, carrying its classes.
+    pub fn from_schedule(schedule: &RequestSchedule) -> Self {
+        let mut recorder = Self::new(schedule.len());
+        recorder.classes = schedule.classes.clone();
+        recorder
+    }
+
+    /// Responses slower than `timeout` are classified as timeouts on conversion.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.sent.len()
+    }
+
+    pub fn anchor(&self) -> Instant {
+        self.anchor.instant
+    }
+
+    pub fn run_anchor(&self) -> RunAnchor {
+        self.anchor
+    }
+
+    fn offset(&self, request_id: usize, time: Instant) -> Result<u64> {
+        if request_id >= self.capacity() {
+            return Err(Error::IdOutOfRange {
+                id: request_id,
+                capacity: self.capacity(),
+            });
+        }
+
+        match time.checked_duration_since(self.anchor.instant) {
+            Some(d) => Ok(d.as_nanos() as u64 + 1),
+            None => Err(Error::BeforeAnchor),
+        }
+    }
+
+    fn instant(&self, offset: u64) -> Option<Instant> {
+        match offset {
+            0 => None,
+            o => Some(self.anchor.instant + Duration::from_nanos(o - 1)),
+        }
+    }
+
+    pub fn record_sent(&self, request_id: usize) -> Result<()> {
+        self.record_sent_at(request_id, Instant::now())
+    }
+
+    pub fn record_sent_at(&self, request_id: usize, time: Instant) -> Result<()> {
+        let offset = self.offset(request_id, time)?;
+        self.sent[request_id].store(offset, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn record_received(&self, request_id: usize) -> Result<()> {
+        self.record_received_at(request_id, Instant::now())
+    }
+
+    pub fn record_received_at(&self, request_id: usize, time: Instant) -> Result<()> {
+        self.record_finished_at(request_id, time, RequestOutcome::Ok)
+    }
+
+    /// Records a response that finished at `time` with the given outcome.
+    pub fn record_finished_at(
+        &self,
+        request_id: usize,
+        time: Instant,
+        outcome: RequestOutcome,
+    ) -> Result<()> {
+        let offset = self.offset(request_id, time)?;
+        self.received[request_id].store(offset, Ordering::Release);
+        self.outcomes[request_id].store(encode_outcome(outcome), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn record_error(&self, request_id: usize, code: u32) -> Result<()> {
+        self.record_finished_at(request_id, Instant::now(), RequestOutcome::Error(code))
+    }
+
+    /// Records an outcome for a request that did not get a response, e.g. `Retried`.
+    pub fn record_outcome(&self, request_id: usize, outcome: RequestOutcome) -> Result<()> {
+        if request_id >= self.capacity() {
+            return Err(Error::IdOutOfRange {
+                id: request_id,
+                capacity: self.capacity(),
+            });
+        }
+        self.outcomes[request_id].store(encode_outcome(outcome), Ordering::Release);
+        Ok(())
+    }
+
+    /// Records how long a request waited for an in-flight limit before it was sent.
+    pub fn record_queue_delay(&self, request_id: usize, delay: Duration) -> Result<()> {
+        if request_id >= self.capacity() {
+            return Err(Error::IdOutOfRange {
+                id: request_id,
+                capacity: self.capacity(),
+            });
+        }
+        self.queue_delays[request_id].store(delay.as_nanos() as u64 + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Records that a request finished now with `outcome`.
+    ///
+    /// `Dropped`, `Retried`, and `Shed` requests have no response, so only their outcome is
+    /// stored.
+    pub fn record_completed(&self, request_id: usize, outcome: RequestOutcome) -> Result<()> {
+        match outcome {
+            RequestOutcome::Dropped | RequestOutcome::Retried | RequestOutcome::Shed => {
+                self.record_outcome(request_id, outcome)
+            }
+            o => self.record_finished_at(request_id, Instant::now(), o),
+        }
+    }
+
+    /// Whether a response or final outcome has been recorded for the request.
+    pub fn is_finished(&self, request_id: usize) -> bool {
+        request_id < self.capacity()
+            && self.outcomes[request_id].load(Ordering::Acquire) != OUTCOME_NONE
+    }
+
+    /// Builds a [`LatencyMap`] from every slot whose send time was recorded.
+    ///
+    /// Requests without a response or outcome are recorded as dropped.
+    pub fn to_latency_map(&self) -> Result<LatencyMap> {
+        let mut map = match self.timeout {
+            Some(timeout) => LatencyMap::with_timeout(timeout),
+            None => LatencyMap::new(),
+        };
+        map.set_anchor(self.anchor);
+
+        for id in 0..self.capacity() {
+            let sent = match self.instant(self.sent[id].load(Ordering::Acquire)) {
+                Some(s) => s,
+                None => continue,
+            };
+            // the outcome is stored after the receive time, so load it first; a receive time
+            // without an outcome is from a response still being recorded, and is ignored
+            let (outcome, received) =
+                match decode_outcome(self.outcomes[id].load(Ordering::Acquire)) {
+                    Some(o) => (o, self.instant(self.received[id].load(Ordering::Acquire))),
+                    None => (RequestOutcome::Dropped, None),
+                };
+            map.record_outcome(id, sent, received, outcome)?;
+            if let Some(class) = self.classes.get(id) {
+                map.set_class(id, *class)?;
+            }
+            match self.queue_delays[id].load(Ordering::Acquire) {
+                0 => (),
+                d => map.set_queue_delay(id, Duration::from_nanos(d - 1))?,
+            }
+        }
+
+        Ok(map)
+    }
+
+    pub fn into_latency_map(self) -> Result<LatencyMap> {
+        self.to_latency_map()
+    }
+
+    pub fn summary_stats(
+        &self,
+        warmup: Duration,
+        cooldown: Duration,
+        use_time_window: bool,
+        histogram_precision: Option<u64>,
+    ) -> Result<SummaryStats> {
+        SummaryStats::new(
+            &self.to_latency_map()?,
+            warmup,
+            cooldown,
+            use_time_window,
+            histogram_precision,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::DistributionType;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn records_from_many_threads() {
+        let recorder = ConcurrentLatencyRecorder::new(800);
+        let recorder = &recorder;
+        std::thread::scope(|s| {
+            for t in 0..8 {
+                s.spawn(move || {
+                    for id in (t * 100)..(t * 100 + 100) {
+                        recorder.record_sent(id).unwrap();
+                        // odd requests are answered by another thread
+                        if id % 2 == 0 {
+                            recorder.record_received(id).unwrap();
+                        }
+                    }
+                });
+                s.spawn(move || {
+                    for id in ((t * 100)..(t * 100 + 100)).filter(|id| id % 2 == 1) {
+                        while recorder.sent[id].load(Ordering::Acquire) == 0 {
+                            std::thread::yield_now();
+                        }
+                        recorder.record_received(id).unwrap();
+                    }
+                });
+            }
+        });
+        assert!((0..800).all(|id| recorder.is_finished(id)));
+        let map = recorder.to_latency_map().unwrap();
+        assert_eq!(map.len(), 800);
+        assert!(map.iter().all(|(_, e)| e.is_ok()));
+    }
+
+    #[test]
+    fn duplicate_receives_overwrite() {
+        let recorder = ConcurrentLatencyRecorder::new(1);
+        let anchor = recorder.anchor();
+        recorder.record_sent_at(0, anchor).unwrap();
+        recorder.record_received_at(0, anchor + ms(1)).unwrap();
+        assert!(recorder.is_finished(0));
+        // callers that want the first response check `is_finished`, as the load generator does
+        recorder.record_received_at(0, anchor + ms(2)).unwrap();
+        let map = recorder.to_latency_map().unwrap();
+        assert_eq!(map.get(0).unwrap().latency(), Some(ms(2)));
+        assert_eq!(map.get(0).unwrap().outcome, RequestOutcome::Ok);
+    }
+
+    #[test]
+    fn rejects_unknown_ids() {
+        let recorder = ConcurrentLatencyRecorder::new(2);
+        let out_of_range =
+            |r: Result<()>| assert!(matches!(r, Err(Error::IdOutOfRange { id: 2, capacity: 2 })));
+        out_of_range(recorder.record_sent(2));
+        out_of_range(recorder.record_received(2));
+        out_of_range(recorder.record_outcome(2, RequestOutcome::Retried));
+        out_of_range(recorder.record_queue_delay(2, ms(1)));
+        assert!(!recorder.is_finished(2));
+    }
+
+    #[test]
+    fn rejects_times_before_the_anchor() {
+        let anchor = Instant::now() + ms(100);
+        let recorder = ConcurrentLatencyRecorder::new_with_anchor(1, anchor);
+        assert!(matches!(recorder.record_sent(0), Err(Error::BeforeAnchor)));
+    }
+
+    #[test]
+    fn receive_before_send() {
+        let recorder = ConcurrentLatencyRecorder::new(2);
+        let anchor = recorder.anchor();
+        // a response for a request never sent isn't in the map
+        recorder.record_received_at(0, anchor + ms(1)).unwrap();
+        assert_eq!(recorder.to_latency_map().unwrap().len(), 0);
+
+        // nor can a response arrive before its request was sent
+        recorder.record_sent_at(1, anchor + ms(2)).unwrap();
+        recorder.record_received_at(1, anchor + ms(1)).unwrap();
+        assert!(matches!(
+            recorder.to_latency_map(),
+            Err(Error::EndBeforeStart { id: 1 })
+        ));
+    }
+
+    #[test]
+    fn slow_responses_become_timeouts() {
+        let recorder = ConcurrentLatencyRecorder::new(3).with_timeout(ms(10));
+        let anchor = recorder.anchor();
+        for id in 0..3 {
+            recorder.record_sent_at(id, anchor).unwrap();
+        }
+        recorder.record_received_at(0, anchor + ms(5)).unwrap();
+        recorder.record_received_at(1, anchor + ms(20)).unwrap();
+        // errors keep their code however slow they are
+        recorder
+            .record_finished_at(2, anchor + ms(20), RequestOutcome::Error(503))
+            .unwrap();
+        let map = recorder.to_latency_map().unwrap();
+        assert_eq!(map.get(0).unwrap().outcome, RequestOutcome::Ok);
+        assert_eq!(map.get(1).unwrap().outcome, RequestOutcome::Timeout);
+        assert_eq!(map.get(2).unwrap().outcome, RequestOutcome::Error(503));
+    }
+
+    #[test]
+    fn outcomes_finish_requests() {
+        let recorder = ConcurrentLatencyRecorder::new(5);
+        for id in 0..5 {
+            recorder.record_sent(id).unwrap();
+            assert!(!recorder.is_finished(id));
+        }
+        recorder.record_outcome(0, RequestOutcome::Retried).unwrap();
+        recorder.record_completed(1, RequestOutcome::Shed).unwrap();
+        recorder
+            .record_completed(2, RequestOutcome::Error(7))
+            .unwrap();
+        recorder.record_error(3, 8).unwrap();
+        assert!((0..4).all(|id| recorder.is_finished(id)));
+        assert!(!recorder.is_finished(4));
+
+        let map = recorder.to_latency_map().unwrap();
+        let entry = |id| map.get(id).unwrap();
+        assert_eq!(entry(0).outcome, RequestOutcome::Retried);
+        assert_eq!(entry(1).outcome, RequestOutcome::Shed);
+        assert!(entry(1).received.is_none());
+        assert_eq!(entry(2).outcome, RequestOutcome::Error(7));
+        assert!(entry(2).received.is_some());
+        assert_eq!(entry(3).outcome, RequestOutcome::Error(8));
+        // unfinished requests are dropped
+        assert_eq!(entry(4).outcome, RequestOutcome::Dropped);
+    }
+
+    #[test]
+    fn receive_time_without_an_outcome_is_ignored() {
+        // a response whose receive time is stored but whose outcome isn't yet, as when the map
+        // is built while a receiver is mid-record
+        let recorder = ConcurrentLatencyRecorder::new(1);
+        recorder.record_sent(0).unwrap();
+        let offset = recorder.offset(0, Instant::now()).unwrap();
+        recorder.received[0].store(offset, Ordering::Release);
+        assert!(!recorder.is_finished(0));
+        let map = recorder.to_latency_map().unwrap();
+        assert_eq!(map.get(0).unwrap().outcome, RequestOutcome::Dropped);
+        assert!(map.get(0).unwrap().received.is_none());
+    }
+
+    #[test]
+    fn carries_classes_and_queue_delays() {
+        let mix = "a:1,b:1".parse().unwrap();
+        let schedule =
+            RequestSchedule::new_with_classes(20, 1000.0, DistributionType::Uniform, &mix, 3)
+                .unwrap();
+        let recorder = ConcurrentLatencyRecorder::from_schedule(&schedule);
+        assert_eq!(recorder.capacity(), 20);
+        for id in 0..20 {
+            recorder.record_sent(id).unwrap();
+        }
+        recorder.record_queue_delay(4, ms(3)).unwrap();
+        let map = recorder.to_latency_map().unwrap();
+        assert!((0..20).all(|id| map.get(id).unwrap().class == schedule.class(id)));
+        assert_eq!(map.get(4).unwrap().queue_delay, Some(ms(3)));
+        assert_eq!(map.get(5).unwrap().queue_delay, None);
+        assert_eq!(map.anchor(), Some(recorder.run_anchor()));
+    }
+}
@@ -0,0 +1,818 @@
+//! Exports results in formats for dashboards and notebooks.
+//!
+//! Every format reports the fields of [`SummaryRow`] and [`RequestRecord`], under the same names.
+//! Latencies and time offsets are in nanoseconds, in fields suffixed `_ns`; the one exception is
+//! Prometheus, whose convention is seconds, and which only uses them in metrics suffixed
+//! `_seconds`. Rates are in requests per second. Each quantile is its own field, `pNN_ns`, named
+//! after its percentile.
+use super::error::{Error, Result};
+use super::histogram::LatencyMap;
+use super::summary_stats::SummaryStats;
+use serde::{Serialize, Serializer};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+pub const DEFAULT_QUANTILES: [f64; 7] = [0.05, 0.25, 0.5, 0.75, 0.95, 0.99, 0.999];
+
+/// Writes summaries and per-request records in one format.
+pub trait Reporter {
+    fn write_summary(&self, stats: &SummaryStats, w: &mut dyn Write) -> Result<()>;
+
+    fn write_requests(&self, map: &LatencyMap, w: &mut dyn Write) -> Result<()>;
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ReportFormat {
+    Json,
+    JsonLines,
+    Csv,
+    Prometheus,
+    Markdown,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<ReportFormat> {
+        Ok(match s {
+            "json" | "JSON" => ReportFormat::Json,
+            "jsonl" | "JSONL" | "json-lines" => ReportFormat::JsonLines,
+            "csv" | "CSV" => ReportFormat::Csv,
+            "prometheus" | "Prometheus" | "openmetrics" | "prom" => ReportFormat::Prometheus,
+            "markdown" | "Markdown" | "md" => ReportFormat::Markdown,
+            x => {
+                return Err(Error::UnknownName {
+                    kind: "report format",
+                    name: x.to_string(),
+                })
+            }
+        })
+    }
+}
+
+impl ReportFormat {
+    pub fn reporter(&self) -> Box<dyn Reporter> {
+        match self {
+            ReportFormat::Json => Box::new(Json::default()),
+            ReportFormat::JsonLines => Box::new(JsonLines::default()),
+            ReportFormat::Csv => Box::new(Csv::default()),
+            ReportFormat::Prometheus => Box::new(Prometheus::default()),
+            ReportFormat::Markdown => Box::new(Markdown::default()),
+        }
+    }
+}
+
+pub fn write_summary_to_file(stats: &SummaryStats, format: ReportFormat, path: &str) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path).map_err(|source| Error::File {
+        path: path.to_string(),
+        source,
+    })?);
+    format.reporter().write_summary(stats, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+pub fn write_requests_to_file(map: &LatencyMap, format: ReportFormat, path: &str) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path).map_err(|source| Error::File {
+        path: path.to_string(),
+        source,
+    })?);
+    format.reporter().write_requests(map, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// One row of a summary: the aggregate or a single request class.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SummaryRow {
+    // "all" for the aggregate, otherwise the class number
+    pub class: String,
+    pub sent: usize,
+    pub ok: usize,
+    pub timeout: usize,
+    pub error: usize,
+    pub dropped: usize,
+    pub retried: usize,
+    pub shed: usize,
+    // requests that waited for an in-flight limit
+    pub queued: usize,
+    // responses whose latency is in the mean and quantiles
+    pub measured: usize,
+    pub offered_load: f64,
+    pub throughput: f64,
+    pub mean_ns: f64,
+    // (quantile, latency in nanoseconds), one per requested quantile; the latency is None when
+    // nothing was measured. Serialized as one `pNN_ns` field per quantile.
+    #[serde(flatten, serialize_with = "serialize_quantiles")]
+    pub quantiles_ns: Vec<(f64, Option<u64>)>,
+}
+
+/// The field or column name for a quantile, e.g. `p99.9_ns`.
+fn quantile_name(quantile: f64) -> String {
+    format!("p{}_ns", quantile * 100.0)
+}
+
+fn serialize_quantiles<S: Serializer>(
+    quantiles: &[(f64, Option<u64>)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(quantiles.iter().map(|(q, lat)| (quantile_name(*q), lat)))
+}
+
+impl SummaryRow {
+    fn new(class: String, stats: &SummaryStats, quantiles: &[f64]) -> Result<Self> {
+        let hist = &stats.histogram;
+        let quantiles_ns = quantiles
+            .iter()
+            .map(|q| {
+                if hist.count == 0 {
+                    Ok((*q, None))
+                } else {
+                    Ok((*q, Some(hist.value_at_quantile(*q)?)))
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(SummaryRow {
+            class,
+            sent: stats.total_objects_sent,
+            ok: stats.outcomes.ok,
+            timeout: stats.outcomes.timeout,
+            error: stats.outcomes.total_errors(),
+            dropped: stats.outcomes.dropped,
+            retried: stats.outcomes.retried,
+            shed: stats.outcomes.shed,
+            queued: stats.queueing.queued,
+            measured: hist.count,
+            offered_load: stats.offered_load(),
+            throughput: stats.throughput(),
+            mean_ns: if hist.count == 0 {
+                0.0
+            } else {
+                latency_sum_ns(stats) / hist.count as f64
+            },
+            quantiles_ns,
+        })
+    }
+
+    /// The table header matching [`SummaryRow::cells`].
+    fn columns(quantiles: &[f64]) -> Vec<String> {
+        let mut columns: Vec<String> = [
+            "class",
+            "sent",
+            "ok",
+            "timeout",
+            "error",
+            "dropped",
+            "retried",
+            "shed",
+            "queued",
+            "measured",
+            "offered_load",
+            "throughput",
+            "mean_ns",
+        ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+        columns.extend(quantiles.iter().map(|q| quantile_name(*q)));
+        columns
+    }
+
+    /// The row as table cells, with `float` formatting rates and the mean, and `missing` standing
+    /// in for quantiles of an empty histogram.
+    fn cells(&self, float: fn(f64) -> String, missing: &str) -> Vec<String> {
+        let mut cells = vec![
+            self.class.clone(),
+            self.sent.to_string(),
+            self.ok.to_string(),
+            self.timeout.to_string(),
+            self.error.to_string(),
+            self.dropped.to_string(),
+            self.retried.to_string(),
+            self.shed.to_string(),
+            self.queued.to_string(),
+            self.measured.to_string(),
+            float(self.offered_load),
+            float(self.throughput),
+            float(self.mean_ns),
+        ];
+        cells.extend(self.quantiles_ns.iter().map(|(_, lat)| match lat {
+            Some(lat) => lat.to_string(),
+            None => missing.to_string(),
+        }));
+        cells
+    }
+}
+
+fn latency_sum_ns(stats: &SummaryStats) -> f64 {
+    stats
+        .histogram
+        .map
+        .iter()
+        .map(|(lat, count)| *lat as f64 * *count as f64)
+        .sum()
+}
+
+/// The aggregate row followed by one row per request class.
+pub fn summary_rows(stats: &SummaryStats, quantiles: &[f64]) -> Result<Vec<SummaryRow>> {
+    let mut rows = vec![SummaryRow::new("all".to_string(), stats, quantiles)?];
+    for (class, class_stats) in stats.classes.iter() {
+        rows.push(SummaryRow::new(class.to_string(), class_stats, quantiles)?);
+    }
+    Ok(rows)
+}
+
+/// One request. Times are offsets in nanoseconds from the first send in the map.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RequestRecord {
+    pub id: usize,
+    pub class: usize,
+    pub outcome: String,
+    pub send_offset_ns: u64,
+    pub recv_offset_ns: Option<u64>,
+    pub latency_ns: Option<u64>,
+}
+
+const REQUEST_COLUMNS: [&str; 6] = [
+    "id",
+    "class",
+    "outcome",
+    "send_offset_ns",
+    "recv_offset_ns",
+    "latency_ns",
+];
+
+impl RequestRecord {
+    /// The record as table cells, in the order of `REQUEST_COLUMNS`.
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.class.to_string(),
+            self.outcome.clone(),
+            self.send_offset_ns.to_string(),
+            opt(self.recv_offset_ns),
+            opt(self.latency_ns),
+        ]
+    }
+}
+
+pub fn request_records(map: &LatencyMap) -> Vec<RequestRecord> {
+    let anchor = map.first_send_time().unwrap_or_else(Instant::now);
+    map.iter()
+        .map(|(id, e)| RequestRecord {
+            id,
+            class: e.class,
+            outcome: e.outcome.to_string(),
+            send_offset_ns: e.sent.duration_since(anchor).as_nanos() as u64,
+            recv_offset_ns: e
+                .received
+                .map(|r| r.duration_since(anchor).as_nanos() as u64),
+            latency_ns: e.latency().map(|l| l.as_nanos() as u64),
+        })
+        .collect()
+}
+
+fn opt<T: std::fmt::Display>(v: Option<T>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// A JSON array of summary rows, or of request records.
+pub struct Json {
+    pub quantiles: Vec<f64>,
+}
+
+impl Default for Json {
+    fn default() -> Self {
+        Json {
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+        }
+    }
+}
+
+impl Reporter for Json {
+    fn write_summary(&self, stats: &SummaryStats, w: &mut dyn Write) -> Result<()> {
+        serde_json::to_writer(&mut *w, &summary_rows(stats, &self.quantiles)?)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    fn write_requests(&self, map: &LatencyMap, w: &mut dyn Write) -> Result<()> {
+        serde_json::to_writer(&mut *w, &request_records(map))?;
+        writeln!(w)?;
+        Ok(())
+    }
+}
+
+/// One JSON object per line: a summary row per class, or a record per request.
+pub struct JsonLines {
+    pub quantiles: Vec<f64>,
+}
+
+impl Default for JsonLines {
+    fn default() -> Self {
+        JsonLines {
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+        }
+    }
+}
+
+impl Reporter for JsonLines {
+    fn write_summary(&self, stats: &SummaryStats, w: &mut dyn Write) -> Result<()> {
+        for row in summary_rows(stats, &self.quantiles)? {
+            serde_json::to_writer(&mut *w, &row)?;
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    fn write_requests(&self, map: &LatencyMap, w: &mut dyn Write) -> Result<()> {
+        for record in request_records(map) {
+            serde_json::to_writer(&mut *w, &record)?;
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+/// A table with one row per class, or one row per request.
+///
+/// Each quantile gets a column such as `p99_ns`; they are empty when nothing was measured.
+pub struct Csv {
+    pub quantiles: Vec<f64>,
+}
+
+impl Default for Csv {
+    fn default() -> Self {
+        Csv {
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+        }
+    }
+}
+
+impl Reporter for Csv {
+    fn write_summary(&self, stats: &SummaryStats, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "{}", SummaryRow::columns(&self.quantiles).join(","))?;
+        for row in summary_rows(stats, &self.quantiles)? {
+            let cells = row.cells(|x| x.to_string(), "");
+            writeln!(w, "{}", cells.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write_requests(&self, map: &LatencyMap, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "{}", REQUEST_COLUMNS.join(","))?;
+        for r in request_records(map) {
+            writeln!(w, "{}", r.cells().join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Prometheus/OpenMetrics text exposition.
+///
+/// Latencies are a summary in seconds, `<prefix>_latency_seconds`, whose count is the `measured`
+/// field; the other fields are counters and gauges named after them. Every series is labelled
+/// with its class, and the aggregate with `class="all"`, so select one or the other rather than
+/// summing over the label.
+pub struct Prometheus {
+    pub prefix: String,
+    pub quantiles: Vec<f64>,
+}
+
+impl Default for Prometheus {
+    fn default() -> Self {
+        Prometheus {
+            prefix: "poisson_ticker".to_string(),
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+        }
+    }
+}
+
+impl Prometheus {
+    fn labels(class: &str, extra: &[(&str, String)]) -> String {
+        let mut labels = vec![format!("class=\"{}\"", class)];
+        for (k, v) in extra {
+            labels.push(format!("{}=\"{}\"", k, v));
+        }
+        format!("{{{}}}", labels.join(","))
+    }
+
+    /// Writes one metric with a value per row.
+    fn metric(
+        &self,
+        w: &mut dyn Write,
+        rows: &[SummaryRow],
+        name: &str,
+        kind: &str,
+        help: &str,
+        value: fn(&SummaryRow) -> f64,
+    ) -> Result<()> {
+        let p = &self.prefix;
+        writeln!(w, "# HELP {}_{} {}", p, name, help)?;
+        writeln!(w, "# TYPE {}_{} {}", p, name, kind)?;
+        let suffix = if kind == "counter" { "_total" } else { "" };
+        for row in rows {
+            writeln!(
+                w,
+                "{}_{}{}{} {}",
+                p,
+                name,
+                suffix,
+                Self::labels(&row.class, &[]),
+                value(row)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Reporter for Prometheus {
+    fn write_summary(&self, stats: &SummaryStats, w: &mut dyn Write) -> Result<()> {
+        let p = &self.prefix;
+        let rows = summary_rows(stats, &self.quantiles)?;
+
+        writeln!(
+            w,
+            "# HELP {}_latency_seconds Latency of successful requests.",
+            p
+        )?;
+        writeln!(w, "# TYPE {}_latency_seconds summary", p)?;
+        for row in rows.iter() {
+            for (q, lat) in row.quantiles_ns.iter() {
+                let lat = match lat {
+                    Some(lat) => lat,
+                    None => continue,
+                };
+                writeln!(
+                    w,
+                    "{}_latency_seconds{} {}",
+                    p,
+                    Self::labels(&row.class, &[("quantile", q.to_string())]),
+                    *lat as f64 / 1e9
+                )?;
+            }
+            let labels = Self::labels(&row.class, &[]);
+            writeln!(
+                w,
+                "{}_latency_seconds_sum{} {}",
+                p,
+                labels,
+                row.mean_ns * row.measured as f64 / 1e9
+            )?;
+            writeln!(w, "{}_latency_seconds_count{} {}", p, labels, row.measured)?;
+        }
+
+        self.metric(w, &rows, "sent", "counter", "Requests sent.", |r| {
+            r.sent as f64
+        })?;
+        writeln!(w, "# HELP {}_requests Requests sent, by outcome.", p)?;
+        writeln!(w, "# TYPE {}_requests counter", p)?;
+        for row in rows.iter() {
+            let counts = [
+                ("ok", row.ok),
+                ("timeout", row.timeout),
+                ("error", row.error),
+                ("dropped", row.dropped),
+                ("retried", row.retried),
+                ("shed", row.shed),
+            ];
+            for (outcome, n) in counts.iter() {
+                writeln!(
+                    w,
+                    "{}_requests_total{} {}",
+                    p,
+                    Self::labels(&row.class, &[("outcome", outcome.to_string())]),
+                    n
+                )?;
+            }
+        }
+        self.metric(
+            w,
+            &rows,
+            "queued",
+            "counter",
+            "Requests that waited for an in-flight limit.",
+            |r| r.queued as f64,
+        )?;
+        self.metric(
+            w,
+            &rows,
+            "offered_load",
+            "gauge",
+            "Requests sent per second.",
+            |r| r.offered_load,
+        )?;
+        self.metric(
+            w,
+            &rows,
+            "throughput",
+            "gauge",
+            "Successful responses per second.",
+            |r| r.throughput,
+        )?;
+        writeln!(w, "# EOF")?;
+        Ok(())
+    }
+
+    fn write_requests(&self, _map: &LatencyMap, _w: &mut dyn Write) -> Result<()> {
+        Err(Error::Unsupported(
+            "Prometheus exposition does not support per-request records".to_string(),
+        ))
+    }
+}
+
+/// A Markdown table with one row per class, or one row per request.
+pub struct Markdown {
+    pub quantiles: Vec<f64>,
+}
+
+impl Default for Markdown {
+    fn default() -> Self {
+        Markdown {
+            quantiles: vec![0.5, 0.9, 0.99, 0.999],
+        }
+    }
+}
+
+/// Writes a Markdown table, right-aligning every column but the ones in `left`.
+fn markdown_table(
+    w: &mut dyn Write,
+    columns: &[String],
+    left: &[&str],
+    rows: impl Iterator<Item = Vec<String>>,
+) -> Result<()> {
+    writeln!(w, "| {} |", columns.join(" | "))?;
+    let align: Vec<&str> = columns
+        .iter()
+        .map(|c| {
+            if left.contains(&c.as_str()) {
+                "---"
+            } else {
+                "---:"
+            }
+        })
+        .collect();
+    writeln!(w, "|{}|", align.join("|"))?;
+    for cells in rows {
+        writeln!(w, "| {} |", cells.join(" | "))?;
+    }
+    Ok(())
+}
+
+impl Reporter for Markdown {
+    fn write_summary(&self, stats: &SummaryStats, w: &mut dyn Write) -> Result<()> {
+        let rows = summary_rows(stats, &self.quantiles)?;
+        markdown_table(
+            w,
+            &SummaryRow::columns(&self.quantiles),
+            &["class"],
+            rows.iter()
+                .map(|row| row.cells(|x| format!("{:.1}", x), "-")),
+        )
+    }
+
+    fn write_requests(&self, map: &LatencyMap, w: &mut dyn Write) -> Result<()> {
+        let columns: Vec<String> = REQUEST_COLUMNS.iter().map(|c| c.to_string()).collect();
+        markdown_table(
+            w,
+            &columns,
+            &["outcome"],
+            request_records(map).iter().map(RequestRecord::cells),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::RequestOutcome;
+    use crate::summary_stats::{test_stats, OutcomeCounts};
+    use std::time::Duration;
+
+    fn stats(latencies: &[u64]) -> SummaryStats {
+        let mut stats = test_stats(10, latencies);
+        stats.send_time = 2.0;
+        stats.receive_time = 2.0;
+        stats.outcomes = OutcomeCounts {
+            ok: latencies.len(),
+            timeout: 1,
+            dropped: 2,
+            retried: 3,
+            shed: 4,
+            errors: vec![(500, 1)].into_iter().collect(),
+        };
+        stats.queueing.queued = 5;
+        stats
+    }
+
+    fn with_class(
+        mut stats: SummaryStats,
+        class: usize,
+        class_stats: SummaryStats,
+    ) -> SummaryStats {
+        stats.classes.insert(class, class_stats);
+        stats
+    }
+
+    fn summary(reporter: &dyn Reporter, stats: &SummaryStats) -> String {
+        let mut out = vec![];
+        reporter.write_summary(stats, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn requests(reporter: &dyn Reporter, map: &LatencyMap) -> String {
+        let mut out = vec![];
+        reporter.write_requests(map, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn map() -> LatencyMap {
+        let start = Instant::now();
+        let ms = Duration::from_millis(1);
+        let mut map = LatencyMap::new();
+        map.record(0, start, Some(start + 2 * ms)).unwrap();
+        map.record_outcome(
+            1,
+            start + ms,
+            Some(start + 4 * ms),
+            RequestOutcome::Error(503),
+        )
+        .unwrap();
+        map.record(2, start + 2 * ms, None).unwrap();
+        map
+    }
+
+    #[test]
+    fn rows_cover_every_class() {
+        let s = with_class(stats(&[1000, 2000]), 3, stats(&[]));
+        let rows = summary_rows(&s, &[0.5]).unwrap();
+        assert_eq!(rows.len(), 2);
+        let all = &rows[0];
+        assert_eq!(all.class, "all");
+        assert_eq!((all.sent, all.ok, all.timeout, all.error), (10, 2, 1, 1));
+        assert_eq!(
+            (all.dropped, all.retried, all.shed, all.queued),
+            (2, 3, 4, 5)
+        );
+        assert_eq!(all.measured, 2);
+        assert_eq!(all.offered_load, 5.0);
+        assert_eq!(all.throughput, 1.0);
+        assert_eq!(all.mean_ns, 1500.0);
+        assert_eq!(all.quantiles_ns, [(0.5, Some(1000))]);
+        // a class with nothing measured has no quantile values rather than an error
+        assert_eq!(rows[1].class, "3");
+        assert_eq!(rows[1].measured, 0);
+        assert_eq!(rows[1].mean_ns, 0.0);
+        assert_eq!(rows[1].quantiles_ns, [(0.5, None)]);
+    }
+
+    #[test]
+    fn file_errors_name_the_path() {
+        let path = std::env::temp_dir()
+            .join("poisson-ticker-missing-dir")
+            .join("summary.json");
+        let path = path.to_str().unwrap();
+        match write_summary_to_file(&stats(&[1000]), ReportFormat::Json, path) {
+            Err(Error::File { path: p, .. }) => assert_eq!(p, path),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn json_formats_share_the_row_schema() {
+        let s = with_class(stats(&[1000]), 1, stats(&[2000]));
+        let rows = summary_rows(&s, &DEFAULT_QUANTILES).unwrap();
+        let expected: Vec<serde_json::Value> = rows
+            .iter()
+            .map(|r| serde_json::to_value(r).unwrap())
+            .collect();
+
+        let json: serde_json::Value = serde_json::from_str(&summary(&Json::default(), &s)).unwrap();
+        assert_eq!(json, serde_json::Value::Array(expected.clone()));
+
+        let lines: Vec<serde_json::Value> = summary(&JsonLines::default(), &s)
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines, expected);
+        assert!(expected[0].get("mean_ns").is_some());
+        assert!(expected[0].get("shed").is_some());
+        // quantiles are named fields, as in the CSV columns
+        assert_eq!(expected[0]["p99.9_ns"], 1000);
+        assert_eq!(expected[1]["p50_ns"], 2000);
+        assert!(expected[0].get("quantiles_ns").is_none());
+    }
+
+    #[test]
+    fn csv_has_a_column_per_field() {
+        let csv = Csv {
+            quantiles: vec![0.5, 0.999],
+        };
+        let out = summary(&csv, &with_class(stats(&[1000]), 2, stats(&[])));
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "class,sent,ok,timeout,error,dropped,retried,shed,queued,measured,\
+             offered_load,throughput,mean_ns,p50_ns,p99.9_ns"
+        );
+        let all: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(all.len(), 15);
+        assert_eq!(
+            &all[..10],
+            &["all", "10", "1", "1", "1", "2", "3", "4", "5", "1"]
+        );
+        assert_eq!(all[12], "1000");
+        assert_eq!(lines[2], "2,10,0,1,1,2,3,4,5,0,5,0,0,,");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn markdown_has_the_same_columns() {
+        let md = Markdown {
+            quantiles: vec![0.5],
+        };
+        let out = summary(&md, &with_class(stats(&[1000]), 2, stats(&[])));
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "| class | sent | ok | timeout | error | dropped | retried | shed | queued | measured \
+             | offered_load | throughput | mean_ns | p50_ns |"
+        );
+        assert_eq!(lines[1].matches('|').count(), 15);
+        assert!(lines[2].starts_with("| all | 10 | 1 | 1 | 1 | 2 | 3 | 4 | 5 | 1 | 5.0 |"));
+        assert!(lines[3].ends_with("| 0.0 | - |"));
+    }
+
+    #[test]
+    fn prometheus_uses_seconds_only_for_latency() {
+        let out = summary(
+            &Prometheus::default(),
+            &with_class(stats(&[2000]), 1, stats(&[])),
+        );
+        assert!(out
+            .contains("poisson_ticker_latency_seconds{class=\"all\",quantile=\"0.5\"} 0.000002\n"));
+        assert!(out.contains("poisson_ticker_latency_seconds_sum{class=\"all\"} 0.000002\n"));
+        assert!(out.contains("poisson_ticker_latency_seconds_count{class=\"all\"} 1\n"));
+        assert!(out.contains("poisson_ticker_latency_seconds_count{class=\"1\"} 0\n"));
+        assert!(!out.contains("poisson_ticker_latency_seconds{class=\"1\",quantile"));
+        assert!(out.contains("poisson_ticker_sent_total{class=\"all\"} 10\n"));
+        assert!(out.contains("poisson_ticker_requests_total{class=\"all\",outcome=\"error\"} 1\n"));
+        assert!(out.contains("poisson_ticker_requests_total{class=\"1\",outcome=\"shed\"} 4\n"));
+        assert!(out.contains("poisson_ticker_queued_total{class=\"all\"} 5\n"));
+        assert!(out.contains("poisson_ticker_offered_load{class=\"all\"} 5\n"));
+        assert!(out.contains("poisson_ticker_throughput{class=\"all\"} 0.5\n"));
+        // every series is labelled, so the aggregate never sums into the classes
+        assert!(out
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .all(|l| l.contains("{class=\"")));
+        assert!(out.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn request_records_in_each_format() {
+        let map = map();
+        let records = request_records(&map);
+        assert_eq!(records[1].outcome, "error:503");
+        assert_eq!(records[1].send_offset_ns, 1_000_000);
+        assert_eq!(records[1].latency_ns, Some(3_000_000));
+        assert_eq!(records[2].latency_ns, None);
+
+        let csv = requests(&Csv::default(), &map);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], REQUEST_COLUMNS.join(","));
+        assert_eq!(lines[1], "0,0,ok,0,2000000,2000000");
+        assert_eq!(lines[3], "2,0,dropped,2000000,,");
+
+        let md = requests(&Markdown::default(), &map);
+        assert!(md.starts_with(
+            "| id | class | outcome | send_offset_ns | recv_offset_ns | latency_ns |\n"
+        ));
+        assert!(md.contains("| 1 | 0 | error:503 | 1000000 | 4000000 | 3000000 |\n"));
+
+        let json: Vec<serde_json::Value> =
+            serde_json::from_str(&requests(&Json::default(), &map)).unwrap();
+        assert_eq!(json.len(), 3);
+        assert_eq!(json[2]["latency_ns"], serde_json::Value::Null);
+        assert_eq!(requests(&JsonLines::default(), &map).lines().count(), 3);
+
+        let mut out = vec![];
+        assert!(matches!(
+            Prometheus::default().write_requests(&map, &mut out),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!(
+            "jsonl".parse::<ReportFormat>().unwrap(),
+            ReportFormat::JsonLines
+        );
+        assert_eq!(
+            "md".parse::<ReportFormat>().unwrap(),
+            ReportFormat::Markdown
+        );
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+}
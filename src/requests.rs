@@ -1,10 +1,19 @@
-use color_eyre::eyre::{bail, Result, WrapErr};
-use rand::thread_rng;
+use super::error::{Error, Result};
+use super::stats::{kolmogorov_survival, mean, stddev};
+use rand::distributions::WeightedIndex;
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
 use rand_distr::{Distribution, Exp};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_json::to_writer;
+#[cfg(feature = "serde")]
+use std::fs::File;
 use std::time::Duration;
 
 #[inline]
 pub fn rate_pps_to_interarrival_nanos(rate: f64) -> f64 {
+    #[cfg(feature = "tracing")]
     tracing::debug!("Nanos intersend: {:?}", 1_000_000_000.0 / rate);
     1_000_000_000.0 / rate
 }
@@ -15,6 +24,7 @@ pub fn nanos_to_hz(hz: u64, nanos: u64) -> u64 {
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DistributionType {
     Uniform,
     Exponential,
@@ -27,14 +37,14 @@ pub enum PacketDistribution {
 }
 
 impl std::str::FromStr for DistributionType {
-    type Err = color_eyre::eyre::Error;
+    type Err = Error;
     fn from_str(s: &str) -> Result<DistributionType> {
         Ok(match s {
             "uniform" | "Uniform" | "UNIFORM" => DistributionType::Uniform,
             "exponential" | "Exponential" | "EXPONENTIAL" | "exp" | "EXP" => {
                 DistributionType::Exponential
             }
-            x => bail!("{} distribution type unknown", x),
+            x => return Err(Error::UnknownDistribution(x.to_string())),
         })
     }
 }
@@ -58,41 +68,198 @@ impl PacketDistribution {
         }
     }
 
-    fn sample(&self) -> u64 {
-        // TODO: how do we know the thread rngs are initialized?
-        let mut rng = thread_rng();
+    fn sample<R: Rng>(&self, rng: &mut R) -> u64 {
         match *self {
             PacketDistribution::Uniform(interarrival_nanos) => interarrival_nanos,
             PacketDistribution::Exponential(l) => {
                 let exp = Exp::new(1.0 / l).expect("Not able to make exponential distribution");
-                exp.sample(&mut rng) as u64
+                exp.sample(rng) as u64
             }
         }
     }
+
+    fn cdf(&self, nanos: f64) -> f64 {
+        match *self {
+            PacketDistribution::Uniform(x) if nanos >= x as f64 => 1.0,
+            PacketDistribution::Uniform(_) => 0.0,
+            PacketDistribution::Exponential(l) => 1.0 - (-nanos.max(0.0) / l).exp(),
+        }
+    }
+
+    // Limit of the CDF from below, which differs from the CDF at the constant interarrival.
+    fn cdf_below(&self, nanos: f64) -> f64 {
+        match *self {
+            PacketDistribution::Uniform(x) if nanos > x as f64 => 1.0,
+            PacketDistribution::Uniform(_) => 0.0,
+            PacketDistribution::Exponential(_) => self.cdf(nanos),
+        }
+    }
+}
+
+/// A weighted mix of request classes, e.g. 90% GETs and 10% PUTs.
+///
+/// Classes are identified by their index in the mix.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RequestClassMix {
+    names: Vec<String>,
+    weights: Vec<f64>,
+}
+
+impl RequestClassMix {
+    pub fn new<S: Into<String>>(classes: impl IntoIterator<Item = (S, f64)>) -> Result<Self> {
+        let (names, weights): (Vec<String>, Vec<f64>) =
+            classes.into_iter().map(|(n, w)| (n.into(), w)).unzip();
+        if names.is_empty() {
+            return Err(Error::InvalidClassMix(
+                "must have at least one class".to_string(),
+            ));
+        }
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+            return Err(Error::InvalidClassMix(format!(
+                "weights must be non-negative: {}",
+                w
+            )));
+        }
+        if weights.iter().sum::<f64>() <= 0.0 {
+            return Err(Error::InvalidClassMix(
+                "weights must not all be zero".to_string(),
+            ));
+        }
+        Ok(RequestClassMix { names, weights })
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name(&self, class: usize) -> Option<&str> {
+        self.names.get(class).map(String::as_str)
+    }
+
+    pub fn class_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+}
+
+impl std::str::FromStr for RequestClassMix {
+    type Err = Error;
+    /// Parses a mix of the form `get:0.9,put:0.1`.
+    fn from_str(s: &str) -> Result<RequestClassMix> {
+        let classes = s
+            .split(',')
+            .map(|c| match c.trim().rsplit_once(':') {
+                Some((name, weight)) => Ok((
+                    name.trim().to_string(),
+                    weight.trim().parse::<f64>().map_err(|_| {
+                        Error::InvalidClassMix(format!("invalid weight for class {}", name))
+                    })?,
+                )),
+                None => Err(Error::InvalidClassMix(format!(
+                    "expected name:weight, got {}",
+                    c
+                ))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        RequestClassMix::new(classes)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RequestSchedule {
     pub interarrivals: Vec<Duration>,
     pub avg_interarrival: u64,
+    // request class for each slot; empty if all requests are class 0
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub classes: Vec<usize>,
 }
 
 impl RequestSchedule {
     pub fn new(num_requests: usize, rate_pps: f64, dist_type: DistributionType) -> Result<Self> {
+        // TODO: how do we know the thread rngs are initialized?
+        Self::generate(num_requests, rate_pps, dist_type, &mut thread_rng())
+    }
+
+    /// Like `new`, but the same seed always produces the same interarrivals.
+    pub fn new_seeded(
+        num_requests: usize,
+        rate_pps: f64,
+        dist_type: DistributionType,
+        seed: u64,
+    ) -> Result<Self> {
+        Self::generate(
+            num_requests,
+            rate_pps,
+            dist_type,
+            &mut StdRng::seed_from_u64(seed),
+        )
+    }
+
+    fn generate<R: Rng>(
+        num_requests: usize,
+        rate_pps: f64,
+        dist_type: DistributionType,
+        rng: &mut R,
+    ) -> Result<Self> {
+        #[cfg(feature = "tracing")]
         tracing::debug!("Initializing packet schedule for {} requests", num_requests);
-        let distribution = PacketDistribution::new(dist_type, rate_pps)
-            .wrap_err("Failed to initialize distribution")?;
+        let distribution = PacketDistribution::new(dist_type, rate_pps)?;
         let mut interarrivals: Vec<Duration> = Vec::with_capacity(num_requests);
         for _ in 0..num_requests {
-            interarrivals.push(Duration::from_nanos(distribution.sample()));
+            interarrivals.push(Duration::from_nanos(distribution.sample(rng)));
         }
 
         Ok(RequestSchedule {
             interarrivals,
             avg_interarrival: distribution.get_interarrival_avg(),
+            classes: Vec::new(),
         })
     }
 
+    /// Like `new_seeded`, with each slot assigned a class drawn from `mix`.
+    ///
+    /// The same seed always produces the same interarrivals and classes.
+    pub fn new_with_classes(
+        num_requests: usize,
+        rate_pps: f64,
+        dist_type: DistributionType,
+        mix: &RequestClassMix,
+        seed: u64,
+    ) -> Result<Self> {
+        // one stream for both, so classes aren't correlated with interarrivals
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut schedule = Self::generate(num_requests, rate_pps, dist_type, &mut rng)?;
+        schedule.draw_classes(mix, &mut rng)?;
+        Ok(schedule)
+    }
+
+    /// Assigns each slot a class drawn from `mix`.
+    ///
+    /// The same mix and seed always produce the same assignment.
+    pub fn assign_classes(&mut self, mix: &RequestClassMix, seed: u64) -> Result<()> {
+        self.draw_classes(mix, &mut StdRng::seed_from_u64(seed))
+    }
+
+    fn draw_classes<R: Rng>(&mut self, mix: &RequestClassMix, rng: &mut R) -> Result<()> {
+        let dist =
+            WeightedIndex::new(mix.weights()).map_err(|e| Error::InvalidClassMix(e.to_string()))?;
+        self.classes = (0..self.len()).map(|_| dist.sample(rng)).collect();
+        Ok(())
+    }
+
+    /// Class of the request in slot `idx`.
+    pub fn class(&self, idx: usize) -> usize {
+        self.classes.get(idx).copied().unwrap_or(0)
+    }
+
     pub fn get_avg_interarrival(&self) -> u64 {
         self.avg_interarrival
     }
@@ -101,7 +268,255 @@ impl RequestSchedule {
         self.interarrivals.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.interarrivals.is_empty()
+    }
+
     pub fn get(&self, idx: usize) -> Duration {
         self.interarrivals[idx]
     }
+
+    /// Time from the first request to the last.
+    pub fn total_duration(&self) -> Duration {
+        self.interarrivals.iter().sum()
+    }
+
+    /// Summarizes the interarrivals and tests how well they fit `dist_type` at the schedule's
+    /// average rate.
+    pub fn inspect(&self, dist_type: DistributionType) -> Result<ScheduleReport> {
+        if self.is_empty() {
+            return Err(Error::EmptySchedule);
+        }
+        if self.avg_interarrival == 0 {
+            return Err(Error::ZeroInterarrival);
+        }
+
+        let mut nanos: Vec<f64> = self
+            .interarrivals
+            .iter()
+            .map(|d| d.as_nanos() as f64)
+            .collect();
+        let mean_interarrival = mean(&nanos);
+        let stddev_interarrival = stddev(&nanos);
+        let cv = match mean_interarrival {
+            m if m > 0.0 => stddev_interarrival / m,
+            _ => 0.0,
+        };
+        let burstiness = match stddev_interarrival + mean_interarrival {
+            t if t > 0.0 => (stddev_interarrival - mean_interarrival) / t,
+            _ => 0.0,
+        };
+
+        // one-sample Kolmogorov-Smirnov test against the requested distribution
+        let distribution =
+            PacketDistribution::new(dist_type, 1_000_000_000.0 / self.avg_interarrival as f64)?;
+        nanos.sort_by(f64::total_cmp);
+        let n = nanos.len() as f64;
+        // compare the CDFs at and just below each distinct value, so ties are handled
+        let mut statistic: f64 = 0.0;
+        let mut i = 0;
+        while i < nanos.len() {
+            let x = nanos[i];
+            let j = i + nanos[i..].iter().take_while(|v| **v == x).count();
+            let below = (i as f64 / n - distribution.cdf_below(x)).abs();
+            let at = (j as f64 / n - distribution.cdf(x)).abs();
+            statistic = statistic.max(below).max(at);
+            i = j;
+        }
+        let en = n.sqrt();
+        let p_value = kolmogorov_survival((en + 0.12 + 0.11 / en) * statistic);
+
+        let total = self.total_duration();
+        Ok(ScheduleReport {
+            num_requests: self.len(),
+            total_duration: total,
+            offered_rate: match total.as_secs_f64() {
+                t if t > 0.0 => self.len() as f64 / t,
+                _ => 0.0,
+            },
+            expected_interarrival: self.avg_interarrival,
+            mean_interarrival,
+            cv,
+            burstiness,
+            fit: GoodnessOfFit {
+                distribution: dist_type,
+                statistic,
+                p_value,
+            },
+        })
+    }
+}
+
+/// Kolmogorov-Smirnov test of a schedule's interarrivals against a distribution.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GoodnessOfFit {
+    pub distribution: DistributionType,
+    // largest distance between the empirical and expected CDFs
+    pub statistic: f64,
+    // probability of a statistic at least this large if the schedule fits
+    pub p_value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScheduleReport {
+    pub num_requests: usize,
+    pub total_duration: Duration,
+    // requests per second over the whole schedule
+    pub offered_rate: f64,
+    // interarrivals in nanoseconds
+    pub expected_interarrival: u64,
+    pub mean_interarrival: f64,
+    // coefficient of variation: 1 for exponential, 0 for uniform
+    pub cv: f64,
+    // (stddev - mean) / (stddev + mean): -1 for periodic, 0 for Poisson, towards 1 for bursty
+    pub burstiness: f64,
+    pub fit: GoodnessOfFit,
+}
+
+impl std::fmt::Display for ScheduleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "requests: {}", self.num_requests)?;
+        writeln!(f, "duration: {:?}", self.total_duration)?;
+        writeln!(f, "offered rate: {:.1} req/s", self.offered_rate)?;
+        writeln!(
+            f,
+            "mean interarrival: {:?} (expected {:?})",
+            Duration::from_nanos(self.mean_interarrival as u64),
+            Duration::from_nanos(self.expected_interarrival)
+        )?;
+        writeln!(f, "cv: {:.4}", self.cv)?;
+        writeln!(f, "burstiness: {:.4}", self.burstiness)?;
+        writeln!(
+            f,
+            "fit to {:?}: KS statistic = {:.4}, p = {:.4}",
+            self.fit.distribution, self.fit.statistic, self.fit.p_value
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn write_to_file(schedule: &RequestSchedule, path: String) -> Result<()> {
+    let file = File::create(&path).map_err(|source| Error::File {
+        path: path.clone(),
+        source,
+    })?;
+    to_writer(&file, schedule).map_err(|source| Error::Json {
+        path: Some(path),
+        source,
+    })
+}
+
+/// Reads a schedule written by `write_to_file`.
+#[cfg(feature = "serde")]
+pub fn read_from_file(path: &str) -> Result<RequestSchedule> {
+    let file = File::open(path).map_err(|source| Error::File {
+        path: path.to_string(),
+        source,
+    })?;
+    serde_json::from_reader(std::io::BufReader::new(file)).map_err(|source| Error::Json {
+        path: Some(path.to_string()),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes_are_seeded() {
+        let mix: RequestClassMix = "get:3,put:1".parse().unwrap();
+        let a =
+            RequestSchedule::new_with_classes(100, 1000.0, DistributionType::Exponential, &mix, 7)
+                .unwrap();
+        let b =
+            RequestSchedule::new_with_classes(100, 1000.0, DistributionType::Exponential, &mix, 7)
+                .unwrap();
+        assert_eq!(a.interarrivals, b.interarrivals);
+        assert_eq!(a.classes, b.classes);
+        assert!(a.classes.iter().all(|c| *c < 2));
+    }
+
+    #[test]
+    fn classes_are_independent_of_interarrivals() {
+        let mix: RequestClassMix = "a:1,b:1".parse().unwrap();
+        let schedule = RequestSchedule::new_with_classes(
+            20000,
+            1000.0,
+            DistributionType::Exponential,
+            &mix,
+            3,
+        )
+        .unwrap();
+        let mut slots: Vec<usize> = (0..schedule.len()).collect();
+        slots.sort_by_key(|i| schedule.get(*i));
+        // the share of class 1 is the same among the shortest and the longest gaps
+        let share = |slots: &[usize]| {
+            slots.iter().filter(|i| schedule.class(**i) == 1).count() as f64 / slots.len() as f64
+        };
+        let (short, long) = slots.split_at(slots.len() / 2);
+        assert!((share(short) - 0.5).abs() < 0.03, "{}", share(short));
+        assert!((share(long) - 0.5).abs() < 0.03, "{}", share(long));
+
+        // nor are they drawn from the numbers the interarrivals were sampled from
+        let mut reused =
+            RequestSchedule::new_seeded(20000, 1000.0, DistributionType::Exponential, 3).unwrap();
+        reused.assign_classes(&mix, 3).unwrap();
+        assert_eq!(reused.interarrivals, schedule.interarrivals);
+        assert_ne!(reused.classes, schedule.classes);
+    }
+
+    #[test]
+    fn exponential_schedule_fits_exponential() {
+        let schedule =
+            RequestSchedule::new_seeded(5000, 1000.0, DistributionType::Exponential, 1).unwrap();
+        let report = schedule.inspect(DistributionType::Exponential).unwrap();
+        assert_eq!(report.num_requests, 5000);
+        assert_eq!(report.expected_interarrival, 1_000_000);
+        assert!((report.cv - 1.0).abs() < 0.1, "cv {}", report.cv);
+        assert!(report.burstiness.abs() < 0.05, "{}", report.burstiness);
+        assert!(report.fit.p_value > 0.01, "{:?}", report.fit);
+        assert!((report.offered_rate - 1000.0).abs() < 50.0);
+    }
+
+    #[test]
+    fn uniform_schedule_fits_only_uniform() {
+        let schedule =
+            RequestSchedule::new_seeded(1000, 1000.0, DistributionType::Uniform, 1).unwrap();
+        let uniform = schedule.inspect(DistributionType::Uniform).unwrap();
+        assert_eq!(uniform.cv, 0.0);
+        assert_eq!(uniform.burstiness, -1.0);
+        assert_eq!(uniform.fit.statistic, 0.0);
+        assert_eq!(uniform.fit.p_value, 1.0);
+
+        let exponential = schedule.inspect(DistributionType::Exponential).unwrap();
+        assert!(exponential.fit.p_value < 1e-6, "{:?}", exponential.fit);
+    }
+
+    #[test]
+    fn exponential_schedule_does_not_fit_uniform() {
+        let schedule =
+            RequestSchedule::new_seeded(1000, 1000.0, DistributionType::Exponential, 1).unwrap();
+        let report = schedule.inspect(DistributionType::Uniform).unwrap();
+        assert!(report.fit.statistic > 0.5, "{:?}", report.fit);
+        assert!(report.fit.p_value < 1e-6, "{:?}", report.fit);
+    }
+
+    #[test]
+    fn inspect_rejects_degenerate_schedules() {
+        let empty =
+            RequestSchedule::new_seeded(0, 1000.0, DistributionType::Exponential, 1).unwrap();
+        assert!(matches!(
+            empty.inspect(DistributionType::Exponential),
+            Err(Error::EmptySchedule)
+        ));
+        let instant =
+            RequestSchedule::new_seeded(10, f64::INFINITY, DistributionType::Uniform, 1).unwrap();
+        assert!(matches!(
+            instant.inspect(DistributionType::Uniform),
+            Err(Error::ZeroInterarrival)
+        ));
+    }
 }
@@ -0,0 +1,46 @@
+//! The accept loop shared by the test servers and the coordinator.
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+// Connection tasks stop with the server.
+// how long to wait after a failed accept before trying again
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+pub(crate) struct AbortOnDrop(pub(crate) JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Accepts connections on `listener` and serves each on its own task. Connections are aborted
+/// when the returned future is dropped or aborted, so a server stops with its accept task.
+///
+/// Accept errors such as running out of file descriptors tend to persist, so the loop backs off
+/// briefly after each rather than spinning.
+pub(crate) async fn accept_loop<F, Fut>(listener: TcpListener, serve: F)
+where
+    F: Fn(TcpStream, SocketAddr) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut connections: Vec<AbortOnDrop> = vec![];
+    loop {
+        match listener.accept().await {
+            Ok((stream, from)) => {
+                connections.retain(|c| !c.0.is_finished());
+                let _ = stream.set_nodelay(true);
+                connections.push(AbortOnDrop(tokio::spawn(serve(stream, from))));
+            }
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(err = %e, "Accept failed");
+                let _ = &e;
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
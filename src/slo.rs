@@ -0,0 +1,456 @@
+//! Service-level objectives, checked against a [`SummaryStats`].
+//!
+//! Objectives can be built directly or parsed from strings such as `p99 < 2ms`,
+//! `drop_rate < 0.1%`, or `achieved_rate >= 0.98 * offered`, which makes them convenient as
+//! performance gates in CI.
+//!
+//! # Example
+//! ```rust
+//! # use poisson_ticker::histogram::LatencyMap;
+//! # use poisson_ticker::slo::Slo;
+//! # use poisson_ticker::summary_stats::SummaryStats;
+//! # use std::time::{Duration, Instant};
+//! let slo: Slo = "p99 < 2ms, drop_rate < 0.1%".parse().unwrap();
+//! // a request every millisecond, each taking 3ms
+//! let start = Instant::now();
+//! let mut map = LatencyMap::new();
+//! for id in 0..100 {
+//!     let sent = start + Duration::from_millis(id as u64);
+//!     map.record(id, sent, Some(sent + Duration::from_millis(3))).unwrap();
+//! }
+//! let zero = Duration::from_secs(0);
+//! let stats = SummaryStats::new(&map, zero, zero, false, None).unwrap();
+//! let report = slo.check(&stats);
+//! assert!(!report.passed());
+//! assert_eq!(report.violations().count(), 1);
+//! ```
+use super::error::{Error, Result};
+use super::summary_stats::SummaryStats;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// A quantity measured from a [`SummaryStats`].
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Metric {
+    // latency at the given quantile, in nanoseconds
+    Latency(f64),
+    // fractions of all requests sent
+    DropRate,
+    ErrorRate,
+    TimeoutRate,
+    // successful responses per second
+    Throughput,
+    // throughput / offered load
+    AchievedFraction,
+}
+
+impl Metric {
+    /// The metric's value, or None if it can't be measured, e.g. a latency when no request
+    /// succeeded.
+    pub fn observe(&self, stats: &SummaryStats) -> Option<f64> {
+        Some(match self {
+            Metric::Latency(q) => stats.histogram.value_at_quantile(*q).ok()? as f64,
+            Metric::DropRate => stats.outcomes.drop_rate(),
+            Metric::ErrorRate => stats.outcomes.error_rate(),
+            Metric::TimeoutRate => stats.outcomes.timeout_rate(),
+            Metric::Throughput => stats.throughput(),
+            Metric::AchievedFraction => match stats.offered_load() {
+                o if o > 0.0 => stats.throughput() / o,
+                _ => 0.0,
+            },
+        })
+    }
+
+    fn format_value(&self, value: f64) -> String {
+        match self {
+            Metric::Latency(_) => format!("{:?}", Duration::from_nanos(value as u64)),
+            Metric::DropRate | Metric::ErrorRate | Metric::TimeoutRate => {
+                format!("{}%", value * 100.0)
+            }
+            Metric::Throughput => format!("{}", value),
+            Metric::AchievedFraction => format!("{} * offered", value),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Metric::Latency(q) => write!(f, "p{}", (q * 1e6).round() / 1e4),
+            Metric::DropRate => write!(f, "drop_rate"),
+            Metric::ErrorRate => write!(f, "error_rate"),
+            Metric::TimeoutRate => write!(f, "timeout_rate"),
+            Metric::Throughput | Metric::AchievedFraction => write!(f, "achieved_rate"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(&self, observed: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Lt => observed < threshold,
+            Comparison::Le => observed <= threshold,
+            Comparison::Gt => observed > threshold,
+            Comparison::Ge => observed >= threshold,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        })
+    }
+}
+
+/// A single objective, e.g. `p99 < 2ms`.
+///
+/// The threshold is in the metric's units: nanoseconds for latencies, fractions for rates.
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Objective {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl Objective {
+    pub fn latency_below(quantile: f64, max: Duration) -> Self {
+        Objective {
+            metric: Metric::Latency(quantile),
+            comparison: Comparison::Lt,
+            threshold: max.as_nanos() as f64,
+        }
+    }
+
+    pub fn drop_rate_below(max: f64) -> Self {
+        Objective {
+            metric: Metric::DropRate,
+            comparison: Comparison::Lt,
+            threshold: max,
+        }
+    }
+
+    pub fn error_rate_below(max: f64) -> Self {
+        Objective {
+            metric: Metric::ErrorRate,
+            comparison: Comparison::Lt,
+            threshold: max,
+        }
+    }
+
+    pub fn achieved_fraction_at_least(fraction: f64) -> Self {
+        Objective {
+            metric: Metric::AchievedFraction,
+            comparison: Comparison::Ge,
+            threshold: fraction,
+        }
+    }
+
+    /// Objectives whose metric can't be measured fail.
+    pub fn check(&self, stats: &SummaryStats) -> ObjectiveResult {
+        let observed = self.metric.observe(stats);
+        ObjectiveResult {
+            objective: *self,
+            observed,
+            passed: observed.is_some_and(|o| self.comparison.holds(o, self.threshold)),
+        }
+    }
+}
+
+impl fmt::Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.metric,
+            self.comparison,
+            self.metric.format_value(self.threshold)
+        )
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration> {
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| Error::InvalidObjective(format!("invalid duration {}", s)))?;
+    let nanos = match unit.trim() {
+        "ns" => num,
+        "us" | "µs" => num * 1e3,
+        "ms" => num * 1e6,
+        "s" => num * 1e9,
+        u => {
+            return Err(Error::InvalidObjective(format!(
+                "unknown duration unit {} in {}",
+                u, s
+            )))
+        }
+    };
+    Ok(Duration::from_nanos(nanos as u64))
+}
+
+fn parse_fraction(s: &str) -> Result<f64> {
+    match s.strip_suffix('%') {
+        Some(pct) => Ok(pct
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| Error::InvalidObjective(format!("invalid percentage {}", s)))?
+            / 100.0),
+        None => s
+            .parse::<f64>()
+            .map_err(|_| Error::InvalidObjective(format!("invalid fraction {}", s))),
+    }
+}
+
+impl std::str::FromStr for Objective {
+    type Err = Error;
+    /// Parses objectives of the form `<metric> <op> <threshold>`, where metric is `pNN`,
+    /// `drop_rate`, `error_rate`, `timeout_rate`, or `achieved_rate`.
+    fn from_str(s: &str) -> Result<Objective> {
+        let s = s.trim();
+        let (op_idx, op_len, comparison) = ["<=", ">=", "<", ">"]
+            .iter()
+            .find_map(|op| s.find(op).map(|i| (i, op.len(), *op)))
+            .ok_or_else(|| Error::InvalidObjective(format!("no comparison in {}", s)))?;
+        let comparison = match comparison {
+            "<=" => Comparison::Le,
+            ">=" => Comparison::Ge,
+            "<" => Comparison::Lt,
+            _ => Comparison::Gt,
+        };
+        let lhs = s[..op_idx].trim();
+        let rhs = s[op_idx + op_len..].trim();
+
+        let (metric, threshold) = match lhs {
+            "drop_rate" => (Metric::DropRate, parse_fraction(rhs)?),
+            "error_rate" => (Metric::ErrorRate, parse_fraction(rhs)?),
+            "timeout_rate" => (Metric::TimeoutRate, parse_fraction(rhs)?),
+            "achieved_rate" | "throughput" => match rhs.split_once('*') {
+                Some((fraction, offered)) if offered.trim() == "offered" => {
+                    (Metric::AchievedFraction, parse_fraction(fraction.trim())?)
+                }
+                Some(_) => {
+                    return Err(Error::InvalidObjective(format!(
+                        "expected `<fraction> * offered`, got {}",
+                        rhs
+                    )))
+                }
+                None => (
+                    Metric::Throughput,
+                    rhs.parse().map_err(|_| {
+                        Error::InvalidObjective(format!("invalid throughput {}", rhs))
+                    })?,
+                ),
+            },
+            p if p.starts_with('p') => {
+                let pct: f64 = p[1..]
+                    .parse()
+                    .map_err(|_| Error::InvalidObjective(format!("invalid quantile {}", p)))?;
+                if !(0.0..=100.0).contains(&pct) {
+                    return Err(Error::InvalidObjective(format!(
+                        "quantile out of range: {}",
+                        p
+                    )));
+                }
+                (
+                    Metric::Latency(pct / 100.0),
+                    parse_duration(rhs)?.as_nanos() as f64,
+                )
+            }
+            m => {
+                return Err(Error::UnknownName {
+                    kind: "metric",
+                    name: m.to_string(),
+                })
+            }
+        };
+
+        Ok(Objective {
+            metric,
+            comparison,
+            threshold,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectiveResult {
+    pub objective: Objective,
+    // None if the metric couldn't be measured
+    pub observed: Option<f64>,
+    pub passed: bool,
+}
+
+impl fmt::Display for ObjectiveResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ",
+            self.objective,
+            if self.passed { "PASS" } else { "FAIL" },
+        )?;
+        match self.observed {
+            Some(o) => write!(f, "(observed {})", self.objective.metric.format_value(o)),
+            None => write!(f, "(not measured)"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SloReport {
+    pub results: Vec<ObjectiveResult>,
+}
+
+impl SloReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+
+    pub fn violations(&self) -> impl Iterator<Item = &ObjectiveResult> {
+        self.results.iter().filter(|r| !r.passed)
+    }
+}
+
+impl fmt::Display for SloReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in self.results.iter() {
+            writeln!(f, "{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of objectives that must all hold.
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Slo {
+    pub objectives: Vec<Objective>,
+}
+
+impl Slo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, objective: Objective) -> Self {
+        self.objectives.push(objective);
+        self
+    }
+
+    pub fn check(&self, stats: &SummaryStats) -> SloReport {
+        let results: Vec<_> = self.objectives.iter().map(|o| o.check(stats)).collect();
+        #[cfg(feature = "tracing")]
+        for r in results.iter().filter(|r| !r.passed) {
+            tracing::warn!(objective = %r.objective, observed = ?r.observed, "SLO violated");
+        }
+        SloReport { results }
+    }
+}
+
+impl std::str::FromStr for Slo {
+    type Err = Error;
+    /// Parses a comma-separated list of objectives.
+    fn from_str(s: &str) -> Result<Slo> {
+        Ok(Slo {
+            objectives: s
+                .split(',')
+                .filter(|o| !o.trim().is_empty())
+                .map(str::parse)
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary_stats::test_stats;
+
+    // one second of 100 requests, `received` of which succeeded in `latency_ns`
+    fn stats(received: usize, latency_ns: u64) -> SummaryStats {
+        test_stats(100, &vec![latency_ns; received])
+    }
+
+    #[test]
+    fn parses_objectives() {
+        let o: Objective = "p99.9 <= 500us".parse().unwrap();
+        assert!(matches!(o.metric, Metric::Latency(q) if (q - 0.999).abs() < 1e-12));
+        assert_eq!(o.comparison, Comparison::Le);
+        assert_eq!(o.threshold, 500_000.0);
+
+        let o: Objective = "drop_rate < 0.1%".parse().unwrap();
+        assert_eq!((o.metric, o.threshold), (Metric::DropRate, 0.001));
+
+        let o: Objective = "achieved_rate >= 0.98 * offered".parse().unwrap();
+        assert_eq!(o, Objective::achieved_fraction_at_least(0.98));
+
+        let o: Objective = "throughput > 1000".parse().unwrap();
+        assert_eq!((o.metric, o.threshold), (Metric::Throughput, 1000.0));
+
+        let slo: Slo = "p50 < 1ms, error_rate < 1%,".parse().unwrap();
+        assert_eq!(slo.objectives.len(), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_objectives() {
+        for s in [
+            "p99 2ms",
+            "p101 < 2ms",
+            "p99 < 2 fortnights",
+            "latency < 2ms",
+            "drop_rate < lots",
+            "achieved_rate >= 0.9 * expected",
+        ] {
+            assert!(s.parse::<Objective>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn checks_each_objective() {
+        let slo = Slo::new()
+            .with(Objective::latency_below(0.99, Duration::from_millis(2)))
+            .with(Objective::drop_rate_below(0.05))
+            .with(Objective::achieved_fraction_at_least(0.9));
+        let report = slo.check(&stats(96, 1_000_000));
+        assert!(report.passed(), "{}", report);
+
+        let report = slo.check(&stats(80, 3_000_000));
+        assert_eq!(report.violations().count(), 3);
+        assert_eq!(
+            report.results[0].to_string(),
+            "p99 < 2ms: FAIL (observed 3ms)"
+        );
+    }
+
+    #[test]
+    fn unmeasurable_latency_fails() {
+        let report = Slo::new()
+            .with(Objective::latency_below(0.99, Duration::from_secs(1)))
+            .check(&stats(0, 0));
+        assert!(!report.passed());
+        assert_eq!(report.results[0].observed, None);
+        assert!(report.to_string().contains("not measured"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Spawns one task per tick of a [`SpinTicker`], recording each into a shared
+//! [`ConcurrentLatencyRecorder`].
+//!
+//! # Example
+//! ```rust
+//! # #[tokio::main]
+//! # async fn main() {
+//! use poisson_ticker::histogram::RequestOutcome;
+//! use poisson_ticker::recorder::ConcurrentLatencyRecorder;
+//! use poisson_ticker::requests::{DistributionType, RequestSchedule};
+//! use poisson_ticker::spawner::{spawn_on_ticks, SpawnConfig};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let schedule = RequestSchedule::new(100, 10_000., DistributionType::Exponential).unwrap();
+//! let recorder = Arc::new(ConcurrentLatencyRecorder::from_schedule(&schedule));
+//! let ticker = poisson_ticker::SpinTicker::new(schedule, Duration::from_secs(1));
+//! let report = spawn_on_ticks(ticker, &recorder, &SpawnConfig::default(), |_id| async {
+//!     RequestOutcome::Ok
+//! })
+//! .await
+//! .unwrap();
+//! assert_eq!(report.spawned, 100);
+//! assert_eq!(recorder.to_latency_map().unwrap().len(), 100);
+//! # }
+//! ```
+use super::error::Result;
+use super::histogram::RequestOutcome;
+use super::limiter::{Admission, InFlightLimit, InFlightLimiter};
+use super::recorder::ConcurrentLatencyRecorder;
+use super::{SpinTicker, Timer};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct SpawnConfig {
+    // how long to wait for outstanding tasks after the last tick;
+    // tasks still running afterwards are aborted and their requests counted as dropped
+    pub grace_period: Duration,
+    // cap on outstanding tasks; unlimited if None
+    pub in_flight_limit: Option<InFlightLimit>,
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        SpawnConfig {
+            grace_period: Duration::from_secs(1),
+            in_flight_limit: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnReport {
+    // one per tick
+    pub spawned: usize,
+    // tasks still running after the grace period
+    pub stragglers: usize,
+}
+
+/// Calls `request` with the schedule index of every tick and spawns the result onto the tokio
+/// runtime, so requests are issued open-loop.
+///
+/// Send times are recorded at each tick and completion times when the future finishes, with
+/// the outcome it returns. Once the ticker is done, waits up to the grace period for
+/// outstanding tasks, then aborts the rest and records them as dropped.
+pub async fn spawn_on_ticks<T, F, Fut>(
+    mut ticker: SpinTicker<T>,
+    recorder: &Arc<ConcurrentLatencyRecorder>,
+    config: &SpawnConfig,
+    request: F,
+) -> Result<SpawnReport>
+where
+    T: Timer + Unpin,
+    F: Fn(usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = RequestOutcome> + Send + 'static,
+{
+    let request = Arc::new(request);
+    let limiter = config
+        .in_flight_limit
+        .map(InFlightLimiter::new)
+        .transpose()?;
+
+    let mut outstanding = Vec::with_capacity(recorder.capacity());
+    while (&mut ticker).await.is_some() {
+        let id = outstanding.len();
+        // arrivals still queued at shutdown are counted as dropped
+        // fails with `IdOutOfRange` if there are more ticks than the recorder has slots
+        recorder.record_sent(id)?;
+        let request = Arc::clone(&request);
+        let recorder = Arc::clone(recorder);
+        let limiter = limiter.clone();
+        outstanding.push(tokio::spawn(async move {
+            let _permit = match limiter {
+                Some(limiter) => match limiter.admit().await {
+                    Admission::Admitted {
+                        permit,
+                        queue_delay,
+                    } => {
+                        if let Some(delay) = queue_delay {
+                            recorder.record_queue_delay(id, delay)?;
+                            recorder.record_sent(id)?;
+                        }
+                        Some(permit)
+                    }
+                    Admission::Shed => {
+                        return recorder.record_outcome(id, RequestOutcome::Shed);
+                    }
+                },
+                None => None,
+            };
+            let outcome = request(id).await;
+            recorder.record_completed(id, outcome)
+        }));
+    }
+
+    let spawned = outstanding.len();
+    // handles before this index have been joined
+    let mut joined = 0;
+    let drained = tokio::time::timeout(config.grace_period, async {
+        for handle in outstanding.iter_mut() {
+            if let Ok(Err(e)) = handle.await {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(err = %e, "Failed to record request");
+                let _ = &e;
+            }
+            joined += 1;
+        }
+    })
+    .await;
+
+    let mut stragglers = 0;
+    if drained.is_err() {
+        let unjoined = outstanding.split_off(joined);
+        for handle in unjoined.iter() {
+            handle.abort();
+        }
+        for (id, handle) in (joined..).zip(unjoined) {
+            // a task only stops at its next await, and may finish recording first, so wait for
+            // it before deciding whether it was dropped
+            let _ = handle.await;
+            if !recorder.is_finished(id) {
+                recorder.record_outcome(id, RequestOutcome::Dropped)?;
+                stragglers += 1;
+            }
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(stragglers, "Requests still outstanding after grace period");
+    }
+
+    Ok(SpawnReport {
+        spawned,
+        stragglers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::{DistributionType, RequestSchedule};
+
+    fn ticker(requests: usize) -> (SpinTicker<crate::SpinTimer>, Arc<ConcurrentLatencyRecorder>) {
+        let schedule = RequestSchedule::new(requests, 10_000., DistributionType::Uniform).unwrap();
+        let recorder = Arc::new(ConcurrentLatencyRecorder::from_schedule(&schedule));
+        (SpinTicker::new(schedule, Duration::from_secs(1)), recorder)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn stragglers_finishing_during_shutdown_are_not_dropped() {
+        let (ticker, recorder) = ticker(3);
+        let config = SpawnConfig {
+            grace_period: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let report = spawn_on_ticks(ticker, &recorder, &config, |id| async move {
+            match id {
+                0 => RequestOutcome::Ok,
+                // still running when it's aborted, and can't stop until it has finished
+                1 => {
+                    tokio::task::yield_now().await;
+                    std::thread::sleep(Duration::from_millis(100));
+                    RequestOutcome::Ok
+                }
+                _ => std::future::pending().await,
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            report,
+            SpawnReport {
+                spawned: 3,
+                stragglers: 1
+            }
+        );
+        let map = recorder.to_latency_map().unwrap();
+        assert!(map.get(0).unwrap().is_ok());
+        assert!(map.get(1).unwrap().is_ok());
+        assert_eq!(map.get(2).unwrap().outcome, RequestOutcome::Dropped);
+        assert!(map.get(2).unwrap().received.is_none());
+    }
+}
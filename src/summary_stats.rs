@@ -1,13 +1,21 @@
-use super::histogram::{LatencyMap, ManualHistogram};
-use color_eyre::eyre::{bail, Result};
+use super::error::{Error, Result};
+use super::histogram::{LatencyMap, ManualHistogram, RequestOutcome};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
 use serde_json::to_writer;
-use std::{collections::BTreeMap, fs::File};
+#[cfg(feature = "serde")]
+use std::fs::File;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
 
 // This takes a manual histogram and stores it with less precision.
 // Useful when rates are very high.
 // When precision is None, is a normal histogram.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SummaryHistogram {
     // Precision in terms of nanoseconds
     // when precision is none, record all items.
@@ -18,20 +26,23 @@ pub struct SummaryHistogram {
     pub count: usize,
 }
 
-impl Default for SummaryHistogram {
-    fn default() -> Self {
-        SummaryHistogram {
-            precision: None,
-            map: BTreeMap::default(),
-            count: 0,
-        }
+// Buckets are `precision` wide, so it must be positive.
+fn check_precision(precision: Option<u64>) -> Result<()> {
+    if precision == Some(0) {
+        return Err(Error::InvalidArgument(
+            "Histogram precision must be positive".to_string(),
+        ));
     }
+    Ok(())
 }
 
 impl SummaryHistogram {
     fn from_manual(precision: Option<u64>, manual_hist: &ManualHistogram) -> Result<Self> {
-        let mut hist = Self::default();
-        hist.precision = precision;
+        check_precision(precision)?;
+        let mut hist = SummaryHistogram {
+            precision,
+            ..Default::default()
+        };
         for lat in manual_hist.latencies_vec().iter() {
             hist.record(*lat);
         }
@@ -44,7 +55,6 @@ impl SummaryHistogram {
             let bucket = (divisor + 1) * precision;
             *self.map.entry(bucket).or_insert(0) += 1;
             self.count += 1;
-            return;
         } else {
             *self.map.entry(latency).or_insert(0) += 1;
             self.count += 1;
@@ -60,55 +70,274 @@ impl SummaryHistogram {
                 return Ok(*lat);
             }
         }
-        bail!("Quantile not found: {:?}", quantile);
+        Err(Error::QuantileNotFound(quantile))
+    }
+
+    /// Adds the counts in `other` to this histogram. Both must use the same precision.
+    pub fn merge(&mut self, other: &SummaryHistogram) -> Result<()> {
+        if self.precision != other.precision {
+            return Err(Error::PrecisionMismatch(self.precision, other.precision));
+        }
+        for (lat, count) in other.map.iter() {
+            *self.map.entry(*lat).or_insert(0) += count;
+        }
+        self.count += other.count;
+        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// Number of requests that finished with each outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OutcomeCounts {
+    pub ok: usize,
+    pub timeout: usize,
+    // Map from error code to count
+    pub errors: BTreeMap<u32, usize>,
+    pub dropped: usize,
+    pub retried: usize,
+    // Requests the client never sent because of an in-flight limit
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shed: usize,
+}
+
+impl OutcomeCounts {
+    pub fn record(&mut self, outcome: RequestOutcome) {
+        match outcome {
+            RequestOutcome::Ok => self.ok += 1,
+            RequestOutcome::Timeout => self.timeout += 1,
+            RequestOutcome::Error(code) => *self.errors.entry(code).or_insert(0) += 1,
+            RequestOutcome::Dropped => self.dropped += 1,
+            RequestOutcome::Retried => self.retried += 1,
+            RequestOutcome::Shed => self.shed += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.ok + self.timeout + self.total_errors() + self.dropped + self.retried + self.shed
+    }
+
+    pub fn total_errors(&self) -> usize {
+        self.errors.values().sum()
+    }
+
+    // Rates are fractions of all requests counted.
+    fn rate(&self, count: usize) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => count as f64 / total as f64,
+        }
+    }
+
+    pub fn ok_rate(&self) -> f64 {
+        self.rate(self.ok)
+    }
+
+    pub fn timeout_rate(&self) -> f64 {
+        self.rate(self.timeout)
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.rate(self.total_errors())
+    }
+
+    pub fn error_rate_for_code(&self, code: u32) -> f64 {
+        self.rate(self.errors.get(&code).copied().unwrap_or(0))
+    }
+
+    pub fn drop_rate(&self) -> f64 {
+        self.rate(self.dropped)
+    }
+
+    pub fn retry_rate(&self) -> f64 {
+        self.rate(self.retried)
+    }
+
+    pub fn shed_rate(&self) -> f64 {
+        self.rate(self.shed)
+    }
+
+    pub fn merge(&mut self, other: &OutcomeCounts) {
+        self.ok += other.ok;
+        self.timeout += other.timeout;
+        for (code, count) in other.errors.iter() {
+            *self.errors.entry(*code).or_insert(0) += count;
+        }
+        self.dropped += other.dropped;
+        self.retried += other.retried;
+        self.shed += other.shed;
+    }
+}
+
+impl std::iter::FromIterator<RequestOutcome> for OutcomeCounts {
+    fn from_iter<I: IntoIterator<Item = RequestOutcome>>(iter: I) -> Self {
+        let mut counts = OutcomeCounts::default();
+        for outcome in iter {
+            counts.record(outcome);
+        }
+        counts
+    }
+}
+
+// How often requests hit a client-side in-flight limit.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QueueingStats {
+    // Number of requests that waited for the limit before being sent
+    pub queued: usize,
+    // Number of requests shed at the limit; also counted in `OutcomeCounts::shed`
+    pub shed: usize,
+    // Time queued requests waited, in nanoseconds
+    pub delay: SummaryHistogram,
+}
+
+impl QueueingStats {
+    /// Number of requests that arrived while the limit was reached.
+    pub fn limit_hits(&self) -> usize {
+        self.queued + self.shed
+    }
+
+    pub fn merge(&mut self, other: &QueueingStats) -> Result<()> {
+        self.queued += other.queued;
+        self.shed += other.shed;
+        self.delay.merge(&other.delay)
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SummaryStats {
+    // Only contains latencies of successful requests
     pub histogram: SummaryHistogram,
     pub total_objects_sent: usize,
+    // Number of successful responses
     pub total_objects_recv: usize,
     pub send_time: f64,
     pub receive_time: f64,
+    // Outcomes of all requests sent within the window
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub outcomes: OutcomeCounts,
+    // Client-side queueing at an in-flight limit, for requests sent within the window
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub queueing: QueueingStats,
+    // Map from request class to stats for that class alone.
+    // Empty if the workload has a single class.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub classes: BTreeMap<usize, SummaryStats>,
 }
 
 impl SummaryStats {
+    /// Computes summary statistics over the requests in `latency_map`.
+    ///
+    /// The measurement window is derived from the recorded send times: requests sent within
+    /// `warmup` of the first send, or within `cooldown` of the last send, are excluded.
     pub fn new(
-        rate_seconds: f64,
-        exp_time_seconds: usize,
         latency_map: &LatencyMap,
-        warmup_time_seconds: usize,
-        cooldown_time_seconds: usize,
+        warmup: Duration,
+        cooldown: Duration,
         use_time_window: bool,
         histogram_precision: Option<u64>,
     ) -> Result<Self> {
+        check_precision(histogram_precision)?;
+        let (first_sent, last_sent) =
+            match (latency_map.first_send_time(), latency_map.last_send_time()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => return Err(Error::EmptyLatencyMap),
+            };
+
         // check warmup and cooldown times are valid
-        if warmup_time_seconds > (exp_time_seconds - cooldown_time_seconds) {
-            bail!("Warmup time must be less than experiment time - cooldown time");
+        let exp_time = last_sent.duration_since(first_sent);
+        if warmup + cooldown >= exp_time {
+            return Err(Error::WindowTooShort {
+                warmup,
+                cooldown,
+                experiment: exp_time,
+            });
         }
 
-        // calculate ID window using the warmup and cooldown times
-        let start_id = (rate_seconds * warmup_time_seconds as f64).ceil() as usize;
-        let mut end_id =
-            (rate_seconds * (exp_time_seconds - cooldown_time_seconds) as f64).floor() as usize;
-
-        // if cooldown is 0, possible that end_id is greater than length of map
-        if end_id > (latency_map.len() - 1) {
-            end_id = latency_map.len() - 1;
-        }
+        // calculate time window using the warmup and cooldown times
+        let start_time = first_sent + warmup;
+        let end_time = last_sent - cooldown;
+        #[cfg(feature = "tracing")]
         tracing::info!(
-            "Start ID: {}, End ID: {}, map len: {}",
-            start_id,
-            end_id,
-            latency_map.len()
+            ?warmup,
+            ?cooldown,
+            ?exp_time,
+            map_len = latency_map.len(),
+            "Computing summary stats"
         );
 
+        let mut stats = Self::from_window(
+            latency_map,
+            start_time,
+            end_time,
+            use_time_window,
+            histogram_precision,
+            None,
+        )?;
+
+        let classes = latency_map.classes();
+        if classes.len() > 1 {
+            for class in classes {
+                // classes only seen during warmup or cooldown have no stats
+                match Self::from_window(
+                    latency_map,
+                    start_time,
+                    end_time,
+                    use_time_window,
+                    histogram_precision,
+                    Some(class),
+                ) {
+                    Ok(class_stats) => {
+                        stats.classes.insert(class, class_stats);
+                    }
+                    Err(Error::EmptyWindow) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn from_window(
+        latency_map: &LatencyMap,
+        start_time: Instant,
+        end_time: Instant,
+        use_time_window: bool,
+        histogram_precision: Option<u64>,
+        class: Option<usize>,
+    ) -> Result<Self> {
         // get histogram, total sent, total recv, sent time, receive time from latency map
-        let (histogram, total_sent, total_recv, send_time, recv_time) =
-            latency_map.histogram_from_id_range(start_id, end_id, use_time_window)?;
+        let (histogram, total_sent, total_recv, send_time, recv_time) = match class {
+            Some(c) => latency_map.class_histogram_from_time_range(
+                start_time,
+                end_time,
+                use_time_window,
+                c,
+            )?,
+            None => latency_map.histogram_from_time_range(start_time, end_time, use_time_window)?,
+        };
 
         let summary_histogram = SummaryHistogram::from_manual(histogram_precision, &histogram)?;
+        let in_window = || {
+            latency_map
+                .entries_in_time_range(start_time, end_time)
+                .filter(|(_, e)| class.is_none_or(|c| e.class == c))
+        };
+        let outcomes: OutcomeCounts = in_window().map(|(_, e)| e.outcome).collect();
+        let mut queueing = QueueingStats {
+            shed: outcomes.shed,
+            delay: SummaryHistogram {
+                precision: histogram_precision,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for delay in in_window().filter_map(|(_, e)| e.queue_delay) {
+            queueing.queued += 1;
+            queueing.delay.record(delay.as_nanos() as u64);
+        }
 
         Ok(SummaryStats {
             histogram: summary_histogram,
@@ -116,11 +345,276 @@ impl SummaryStats {
             total_objects_recv: total_recv,
             send_time,
             receive_time: recv_time,
+            outcomes,
+            queueing,
+            classes: BTreeMap::new(),
         })
     }
+
+    /// Successful responses per second.
+    pub fn throughput(&self) -> f64 {
+        if self.receive_time > 0.0 {
+            self.total_objects_recv as f64 / self.receive_time
+        } else {
+            0.0
+        }
+    }
+
+    /// Requests sent per second.
+    pub fn offered_load(&self) -> f64 {
+        if self.send_time > 0.0 {
+            self.total_objects_sent as f64 / self.send_time
+        } else {
+            0.0
+        }
+    }
+
+    /// Combines stats from runs that happened at the same time, e.g. one per client machine.
+    ///
+    /// Histograms and counts are summed. The runs are assumed to overlap, so the send and
+    /// receive times are the longest of any run, and throughput is the aggregate across runs.
+    pub fn merge(stats: &[SummaryStats]) -> Result<Self> {
+        let (first, rest) = match stats.split_first() {
+            Some(s) => s,
+            None => return Err(Error::NothingToMerge),
+        };
+
+        let mut merged = first.clone();
+        for s in rest {
+            merged.histogram.merge(&s.histogram)?;
+            merged.total_objects_sent += s.total_objects_sent;
+            merged.total_objects_recv += s.total_objects_recv;
+            merged.send_time = merged.send_time.max(s.send_time);
+            merged.receive_time = merged.receive_time.max(s.receive_time);
+            merged.outcomes.merge(&s.outcomes);
+            merged.queueing.merge(&s.queueing)?;
+            for (class, class_stats) in s.classes.iter() {
+                let merged_class = match merged.classes.remove(class) {
+                    Some(c) => Self::merge(&[c, class_stats.clone()])?,
+                    None => class_stats.clone(),
+                };
+                merged.classes.insert(*class, merged_class);
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Fraction of requests that arrived while the in-flight limit was reached.
+    pub fn limit_hit_rate(&self) -> f64 {
+        match self.outcomes.total() {
+            0 => 0.0,
+            total => self.queueing.limit_hits() as f64 / total as f64,
+        }
+    }
+
+    /// Stats for a single request class. With a single-class workload, class 0 is the aggregate.
+    pub fn class(&self, class: usize) -> Option<&SummaryStats> {
+        match (self.classes.get(&class), class) {
+            (Some(stats), _) => Some(stats),
+            (None, 0) if self.classes.is_empty() => Some(self),
+            _ => None,
+        }
+    }
 }
 
+// Activity within one window of a run's timeline.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TimelineWindow {
+    // seconds since the first send
+    pub start: f64,
+    pub end: f64,
+    // Number of requests sent within the window
+    pub sent: usize,
+    // Number of successful responses received within the window
+    pub completed: usize,
+    // Outcomes of requests sent within the window
+    pub outcomes: OutcomeCounts,
+    // Latencies of successful responses received within the window
+    pub histogram: SummaryHistogram,
+}
+
+impl TimelineWindow {
+    /// Successful responses per second.
+    pub fn throughput(&self) -> f64 {
+        self.completed as f64 / (self.end - self.start)
+    }
+
+    /// Requests sent per second.
+    pub fn offered_load(&self) -> f64 {
+        self.sent as f64 / (self.end - self.start)
+    }
+}
+
+/// Splits a run into consecutive windows of length `window`, starting at the first send.
+///
+/// Requests are counted in the window they were sent in; responses and their latencies in the
+/// window they were received in.
+pub fn timeline(
+    latency_map: &LatencyMap,
+    window: Duration,
+    histogram_precision: Option<u64>,
+) -> Result<Vec<TimelineWindow>> {
+    if window == Duration::from_secs(0) {
+        return Err(Error::InvalidWindow);
+    }
+    check_precision(histogram_precision)?;
+    let first_sent = match latency_map.first_send_time() {
+        Some(f) => f,
+        None => return Err(Error::EmptyLatencyMap),
+    };
+    let last = latency_map
+        .iter()
+        .map(|(_, e)| e.received.unwrap_or(e.sent).max(e.sent))
+        .max()
+        .unwrap_or(first_sent);
+
+    let window_nanos = window.as_nanos();
+    let index = |t: Instant| (t.duration_since(first_sent).as_nanos() / window_nanos) as usize;
+    let window_secs = window.as_secs_f64();
+    let mut windows: Vec<TimelineWindow> = (0..=index(last))
+        .map(|i| TimelineWindow {
+            start: i as f64 * window_secs,
+            end: (i + 1) as f64 * window_secs,
+            sent: 0,
+            completed: 0,
+            outcomes: OutcomeCounts::default(),
+            histogram: SummaryHistogram {
+                precision: histogram_precision,
+                ..Default::default()
+            },
+        })
+        .collect();
+
+    for (_, e) in latency_map.iter() {
+        let w = &mut windows[index(e.sent)];
+        w.sent += 1;
+        w.outcomes.record(e.outcome);
+        if let (true, Some(received), Some(latency)) = (e.is_ok(), e.received, e.latency()) {
+            let w = &mut windows[index(received)];
+            w.completed += 1;
+            w.histogram.record(latency.as_nanos() as u64);
+        }
+    }
+
+    Ok(windows)
+}
+
+#[cfg(feature = "serde")]
 pub fn write_to_file(summary_stats: &SummaryStats, path: String) -> Result<()> {
-    to_writer(&File::create(&path)?, summary_stats)?;
-    Ok(())
+    let file = File::create(&path).map_err(|source| Error::File {
+        path: path.clone(),
+        source,
+    })?;
+    to_writer(&file, summary_stats).map_err(|source| Error::Json {
+        path: Some(path),
+        source,
+    })
+}
+
+/// Reads summary stats written by `write_to_file`.
+#[cfg(feature = "serde")]
+pub fn read_from_file(path: &str) -> Result<SummaryStats> {
+    let file = File::open(path).map_err(|source| Error::File {
+        path: path.to_string(),
+        source,
+    })?;
+    serde_json::from_reader(std::io::BufReader::new(file)).map_err(|source| Error::Json {
+        path: Some(path.to_string()),
+        source,
+    })
+}
+
+/// Test fixture: stats for `sent` requests over one second, with a successful response for each
+/// latency in `latencies_ns` and the rest dropped.
+#[cfg(test)]
+pub(crate) fn test_stats(sent: usize, latencies_ns: &[u64]) -> SummaryStats {
+    let mut histogram = SummaryHistogram::default();
+    for latency in latencies_ns {
+        histogram.record(*latency);
+    }
+    SummaryStats {
+        histogram,
+        total_objects_sent: sent,
+        total_objects_recv: latencies_ns.len(),
+        send_time: 1.0,
+        receive_time: 1.0,
+        outcomes: OutcomeCounts {
+            ok: latencies_ns.len(),
+            dropped: sent.saturating_sub(latencies_ns.len()),
+            ..Default::default()
+        },
+        queueing: Default::default(),
+        classes: Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one request every millisecond, each answered after 100us, in the given classes
+    fn map_with_classes(classes: &[usize]) -> LatencyMap {
+        let start = Instant::now();
+        let mut map = LatencyMap::new();
+        for (id, class) in classes.iter().enumerate() {
+            let sent = start + Duration::from_millis(id as u64);
+            map.record(id, sent, Some(sent + Duration::from_micros(100)))
+                .unwrap();
+            map.set_class(id, *class).unwrap();
+        }
+        map
+    }
+
+    #[test]
+    fn skips_classes_outside_the_window() {
+        // class 2 is only sent during warmup
+        let mut classes = [0, 1].repeat(50);
+        classes[0] = 2;
+        let map = map_with_classes(&classes);
+        let stats = SummaryStats::new(
+            &map,
+            Duration::from_millis(10),
+            Duration::from_millis(10),
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(stats.classes.keys().copied().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(
+            stats.classes[&0].total_objects_sent + stats.classes[&1].total_objects_sent,
+            stats.total_objects_sent
+        );
+    }
+
+    #[test]
+    fn window_must_fit_in_experiment() {
+        let map = map_with_classes(&[0; 10]);
+        let ms = Duration::from_millis(5);
+        assert!(matches!(
+            SummaryStats::new(&map, ms, ms, true, None),
+            Err(Error::WindowTooShort { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_zero_precision() {
+        let map = map_with_classes(&[0; 10]);
+        let zero = Duration::from_secs(0);
+        assert!(matches!(
+            SummaryStats::new(&map, zero, zero, true, Some(0)),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            timeline(&map, Duration::from_millis(1), Some(0)),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // latencies are rounded up to the next multiple of the precision
+        let stats = SummaryStats::new(&map, zero, zero, true, Some(1000)).unwrap();
+        assert_eq!(
+            stats.histogram.map.keys().copied().collect::<Vec<_>>(),
+            [101_000]
+        );
+    }
 }
@@ -0,0 +1,367 @@
+//! Load-latency curves: run a workload at increasing offered load and record how latency and
+//! achieved throughput respond.
+use super::error::{Error, Result};
+#[cfg(feature = "async")]
+use super::histogram::RequestOutcome;
+#[cfg(feature = "async")]
+use super::limiter::InFlightLimit;
+#[cfg(feature = "async")]
+use super::recorder::ConcurrentLatencyRecorder;
+#[cfg(feature = "async")]
+use super::requests::{DistributionType, RequestSchedule};
+#[cfg(feature = "async")]
+use super::spawner::{spawn_on_ticks, SpawnConfig};
+use super::summary_stats::SummaryStats;
+#[cfg(feature = "async")]
+use super::SpinTicker;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_json::to_writer;
+use std::fs::File;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::Write;
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The offered loads to run, in requests per second.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RateSweep {
+    List(Vec<f64>),
+    // start, start * factor, start * factor^2, ... up to and including max
+    Geometric { start: f64, factor: f64, max: f64 },
+}
+
+impl RateSweep {
+    pub fn rates(&self) -> Result<Vec<f64>> {
+        match self {
+            RateSweep::List(rates) => {
+                if let Some(r) = rates.iter().find(|r| !(r.is_finite() && **r > 0.0)) {
+                    return Err(Error::InvalidArgument(format!(
+                        "Rates must be positive: {}",
+                        r
+                    )));
+                }
+                Ok(rates.clone())
+            }
+            RateSweep::Geometric { start, factor, max } => {
+                if !(start.is_finite() && *start > 0.0) {
+                    return Err(Error::InvalidArgument(format!(
+                        "Sweep start rate must be positive: {}",
+                        start
+                    )));
+                }
+                if !(factor.is_finite() && *factor > 1.0) {
+                    return Err(Error::InvalidArgument(format!(
+                        "Sweep factor must be greater than 1: {}",
+                        factor
+                    )));
+                }
+                let mut rates = vec![];
+                let mut rate = *start;
+                while rate <= *max {
+                    rates.push(rate);
+                    rate *= factor;
+                }
+                Ok(rates)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug, Clone)]
+pub struct SweepConfig {
+    pub rates: RateSweep,
+    // how long to send at each rate
+    pub duration: Duration,
+    pub warmup: Duration,
+    pub cooldown: Duration,
+    // how long to wait for outstanding responses after the last send;
+    // requests still outstanding afterwards are counted as dropped
+    pub drain: Duration,
+    pub distribution: DistributionType,
+    pub histogram_precision: Option<u64>,
+    pub timeout: Option<Duration>,
+    // stop the sweep once p99 latency exceeds this
+    pub max_p99: Option<Duration>,
+    // stop the sweep once the fraction of dropped requests exceeds this
+    pub max_drop_rate: Option<f64>,
+    // cap on outstanding requests at each rate; unlimited if None
+    pub in_flight_limit: Option<InFlightLimit>,
+}
+
+#[cfg(feature = "async")]
+impl Default for SweepConfig {
+    fn default() -> Self {
+        SweepConfig {
+            rates: RateSweep::List(vec![]),
+            duration: Duration::from_secs(10),
+            warmup: Duration::from_secs(1),
+            cooldown: Duration::from_secs(1),
+            drain: Duration::from_secs(1),
+            distribution: DistributionType::Exponential,
+            histogram_precision: None,
+            timeout: None,
+            max_p99: None,
+            max_drop_rate: None,
+            in_flight_limit: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SweepPoint {
+    pub offered_rate: f64,
+    // successful responses per second
+    pub achieved_rate: f64,
+    pub stats: SummaryStats,
+}
+
+impl SweepPoint {
+    /// Uses the offered load measured in `stats` as the point's rate.
+    pub fn from_stats(stats: SummaryStats) -> Self {
+        SweepPoint {
+            offered_rate: stats.offered_load(),
+            achieved_rate: stats.throughput(),
+            stats,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StopReason {
+    P99Exceeded { rate: f64, p99: Duration },
+    DropRateExceeded { rate: f64, drop_rate: f64 },
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SweepResult {
+    pub points: Vec<SweepPoint>,
+    // why the sweep ended before the last rate, if it did
+    pub stopped_early: Option<StopReason>,
+}
+
+/// One row of the load-latency curve. Latencies are in nanoseconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CurveRow {
+    pub offered_rate: f64,
+    pub achieved_rate: f64,
+    // (quantile, latency)
+    pub latencies: Vec<(f64, u64)>,
+}
+
+impl SweepResult {
+    pub fn curve(&self, quantiles: &[f64]) -> Result<Vec<CurveRow>> {
+        self.points
+            .iter()
+            .map(|p| {
+                Ok(CurveRow {
+                    offered_rate: p.offered_rate,
+                    achieved_rate: p.achieved_rate,
+                    latencies: quantiles
+                        .iter()
+                        .map(|q| Ok((*q, p.stats.histogram.value_at_quantile(*q)?)))
+                        .collect::<Result<_>>()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Logs the curve as a whitespace-separated table, one row per rate, with latencies in
+    /// microseconds.
+    pub fn log_curve_to_file(&self, path: &str, quantiles: &[f64]) -> Result<()> {
+        let mut file = File::create(path)?;
+        write!(file, "Offered_pps Achieved_pps")?;
+        for q in quantiles {
+            write!(file, " p{}_us", q * 100.0)?;
+        }
+        writeln!(file)?;
+        for row in self.curve(quantiles)? {
+            write!(file, "{} {}", row.offered_rate, row.achieved_rate)?;
+            for (_, lat) in row.latencies {
+                write!(file, " {}", lat as f64 / 1_000.0)?;
+            }
+            writeln!(file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn write_to_file(result: &SweepResult, path: String) -> Result<()> {
+    let file = File::create(&path).map_err(|source| Error::File {
+        path: path.clone(),
+        source,
+    })?;
+    to_writer(&file, result).map_err(|source| Error::Json {
+        path: Some(path),
+        source,
+    })
+}
+
+/// Runs `request` under a [`SpinTicker`] at each rate in the sweep.
+///
+/// `request` is called with the request ID (the index in the schedule) and is spawned onto the
+/// tokio runtime, so requests are issued open-loop. The sweep stops early once a point exceeds
+/// the p99 or drop-rate threshold.
+#[cfg(feature = "async")]
+pub async fn run_sweep<F, Fut>(config: &SweepConfig, request: F) -> Result<SweepResult>
+where
+    F: Fn(usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = RequestOutcome> + Send + 'static,
+{
+    let request = Arc::new(request);
+    let mut result = SweepResult {
+        points: vec![],
+        stopped_early: None,
+    };
+
+    for rate in config.rates.rates()? {
+        let stats = run_rate(config, rate, Arc::clone(&request))
+            .await
+            .map_err(|e| Error::AtRate {
+                rate,
+                source: Box::new(e),
+            })?;
+        let p99 = Duration::from_nanos(stats.histogram.value_at_quantile(0.99).unwrap_or(0));
+        let drop_rate = stats.outcomes.drop_rate();
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            offered = rate,
+            achieved = stats.throughput(),
+            ?p99,
+            drop_rate,
+            "Finished sweep point"
+        );
+
+        result.points.push(SweepPoint {
+            offered_rate: rate,
+            achieved_rate: stats.throughput(),
+            stats,
+        });
+
+        if let Some(max_p99) = config.max_p99 {
+            if p99 > max_p99 {
+                result.stopped_early = Some(StopReason::P99Exceeded { rate, p99 });
+                break;
+            }
+        }
+        if let Some(max_drop_rate) = config.max_drop_rate {
+            if drop_rate > max_drop_rate {
+                result.stopped_early = Some(StopReason::DropRateExceeded { rate, drop_rate });
+                break;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(feature = "async")]
+async fn run_rate<F, Fut>(config: &SweepConfig, rate: f64, request: Arc<F>) -> Result<SummaryStats>
+where
+    F: Fn(usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = RequestOutcome> + Send + 'static,
+{
+    let num_requests = (rate * config.duration.as_secs_f64()).ceil() as usize;
+    let schedule = RequestSchedule::new(num_requests, rate, config.distribution)?;
+    let mut recorder = ConcurrentLatencyRecorder::from_schedule(&schedule);
+    if let Some(timeout) = config.timeout {
+        recorder = recorder.with_timeout(timeout);
+    }
+    let recorder = Arc::new(recorder);
+
+    let spawn_config = SpawnConfig {
+        grace_period: config.drain,
+        in_flight_limit: config.in_flight_limit,
+    };
+    let ticker = SpinTicker::new(schedule, config.duration);
+    spawn_on_ticks(ticker, &recorder, &spawn_config, move |id| request(id)).await?;
+
+    recorder.summary_stats(
+        config.warmup,
+        config.cooldown,
+        true,
+        config.histogram_precision,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometric_rates() {
+        let sweep = RateSweep::Geometric {
+            start: 100.0,
+            factor: 2.0,
+            max: 1000.0,
+        };
+        assert_eq!(sweep.rates().unwrap(), [100.0, 200.0, 400.0, 800.0]);
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        assert!(RateSweep::List(vec![100.0, 0.0]).rates().is_err());
+        assert!(RateSweep::List(vec![f64::NAN]).rates().is_err());
+        let sweep = RateSweep::Geometric {
+            start: 100.0,
+            factor: 1.0,
+            max: 1000.0,
+        };
+        assert!(sweep.rates().is_err());
+    }
+
+    #[cfg(feature = "async")]
+    fn config(rates: Vec<f64>) -> SweepConfig {
+        SweepConfig {
+            rates: RateSweep::List(rates),
+            duration: Duration::from_millis(200),
+            warmup: Duration::from_millis(20),
+            cooldown: Duration::from_millis(20),
+            drain: Duration::from_millis(100),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn runs_each_rate() {
+        let result = run_sweep(&config(vec![200.0, 400.0]), |_| async {
+            RequestOutcome::Ok
+        })
+        .await
+        .unwrap();
+        assert!(result.stopped_early.is_none());
+        let offered: Vec<_> = result.points.iter().map(|p| p.offered_rate).collect();
+        assert_eq!(offered, [200.0, 400.0]);
+        assert!(result.points.iter().all(|p| p.stats.outcomes.ok > 0));
+        let curve = result.curve(&[0.5]).unwrap();
+        assert_eq!(curve.len(), 2);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn stops_once_requests_drop() {
+        let config = SweepConfig {
+            max_drop_rate: Some(0.5),
+            ..config(vec![200.0, 400.0])
+        };
+        let result = run_sweep(&config, |_| async { RequestOutcome::Dropped })
+            .await
+            .unwrap();
+        assert_eq!(result.points.len(), 1);
+        assert!(matches!(
+            result.stopped_early,
+            Some(StopReason::DropRateExceeded { rate, .. }) if rate == 200.0
+        ));
+    }
+}
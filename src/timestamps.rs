@@ -0,0 +1,554 @@
+//! Persists absolute send and receive times, so timelines and time-windowed statistics can be
+//! recomputed offline.
+//!
+//! `Instant`s can't be serialized, so times are written as nanosecond offsets from a
+//! [`RunAnchor`], which pairs a monotonic `Instant` with the wall-clock time at the same moment.
+//! The wall-clock half lets runs from different machines be aligned with each other.
+//!
+//! Two formats are supported: a compact little-endian binary format and a CSV fallback.
+//! `read_timestamps` detects which one a file uses.
+use super::error::{Error, Result};
+pub use super::histogram::RunAnchor;
+use super::histogram::{LatencyMap, RequestOutcome};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"PTTS";
+const VERSION: u16 = 1;
+// id, send offset, recv offset, class, outcome kind, error code
+const RECORD_LEN: usize = 8 + 8 + 8 + 4 + 4 + 4;
+const NO_RECV: u64 = u64::MAX;
+const CSV_ANCHOR_PREFIX: &str = "# anchor_unix_nanos=";
+const CSV_HEADER: &str = "id,class,outcome,send_offset_ns,recv_offset_ns";
+
+// an offset from `anchor`, which must be at or before `time`
+fn offset(anchor: &RunAnchor, time: Instant) -> Result<u64> {
+    match time.checked_duration_since(anchor.instant) {
+        Some(d) => Ok(d.as_nanos() as u64),
+        None => Err(Error::BeforeAnchor),
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum TimestampFormat {
+    Binary,
+    Csv,
+}
+
+impl std::str::FromStr for TimestampFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<TimestampFormat> {
+        Ok(match s {
+            "binary" | "Binary" | "bin" => TimestampFormat::Binary,
+            "csv" | "CSV" => TimestampFormat::Csv,
+            x => {
+                return Err(Error::UnknownName {
+                    kind: "timestamp format",
+                    name: x.to_string(),
+                })
+            }
+        })
+    }
+}
+
+/// One request, with times as nanosecond offsets from the run anchor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampRecord {
+    pub id: usize,
+    pub class: usize,
+    pub outcome: RequestOutcome,
+    pub send_offset_ns: u64,
+    pub recv_offset_ns: Option<u64>,
+}
+
+/// The contents of a timestamp file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampLog {
+    pub anchor_unix_nanos: u64,
+    pub records: Vec<TimestampRecord>,
+}
+
+impl TimestampLog {
+    /// Records every request in `map` relative to the map's anchor.
+    ///
+    /// If the map has no anchor, one is inferred at its first send time.
+    pub fn from_latency_map(map: &LatencyMap) -> Result<Self> {
+        let anchor = match (map.anchor(), map.first_send_time()) {
+            (Some(a), _) => a,
+            (None, Some(first)) => RunAnchor::from_instant(first),
+            (None, None) => RunAnchor::now(),
+        };
+        let records = map
+            .iter()
+            .map(|(id, e)| {
+                Ok(TimestampRecord {
+                    id,
+                    class: e.class,
+                    outcome: e.outcome,
+                    send_offset_ns: offset(&anchor, e.sent)?,
+                    recv_offset_ns: e.received.map(|r| offset(&anchor, r)).transpose()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(TimestampLog {
+            anchor_unix_nanos: anchor.unix_nanos,
+            records,
+        })
+    }
+
+    /// Rebuilds a [`LatencyMap`] whose anchor is a fresh `Instant` at the logged wall-clock time.
+    pub fn to_latency_map(&self) -> Result<LatencyMap> {
+        self.to_latency_map_at(RunAnchor {
+            instant: Instant::now(),
+            unix_nanos: self.anchor_unix_nanos,
+        })
+    }
+
+    /// Rebuilds a [`LatencyMap`] aligned by wall-clock time to `reference`, so that maps loaded
+    /// from different machines against the same reference share a timeline.
+    ///
+    /// The log's anchor may be before or after the reference; it's only an error if it's earlier
+    /// than the reference `Instant` can represent.
+    pub fn to_latency_map_aligned(&self, reference: &RunAnchor) -> Result<LatencyMap> {
+        let instant = if self.anchor_unix_nanos >= reference.unix_nanos {
+            let shift = Duration::from_nanos(self.anchor_unix_nanos - reference.unix_nanos);
+            reference.instant.checked_add(shift).ok_or_else(|| {
+                Error::MalformedTimestamps(format!(
+                    "anchor {} is out of range",
+                    self.anchor_unix_nanos
+                ))
+            })?
+        } else {
+            let shift = Duration::from_nanos(reference.unix_nanos - self.anchor_unix_nanos);
+            reference
+                .instant
+                .checked_sub(shift)
+                .ok_or(Error::BeforeAnchor)?
+        };
+        self.to_latency_map_at(RunAnchor {
+            instant,
+            unix_nanos: self.anchor_unix_nanos,
+        })
+    }
+
+    fn to_latency_map_at(&self, anchor: RunAnchor) -> Result<LatencyMap> {
+        let mut map = LatencyMap::new();
+        map.set_anchor(anchor);
+        let at = |offset_ns: u64| {
+            anchor
+                .instant
+                .checked_add(Duration::from_nanos(offset_ns))
+                .ok_or_else(|| {
+                    Error::MalformedTimestamps(format!("offset {}ns is out of range", offset_ns))
+                })
+        };
+        for r in self.records.iter() {
+            let sent = at(r.send_offset_ns)?;
+            let received = r.recv_offset_ns.map(at).transpose()?;
+            map.record_outcome(r.id, sent, received, r.outcome)?;
+            map.set_class(r.id, r.class)?;
+        }
+        Ok(map)
+    }
+
+    pub fn write(&self, w: &mut impl Write, format: TimestampFormat) -> Result<()> {
+        match format {
+            TimestampFormat::Binary => self.write_binary(w),
+            TimestampFormat::Csv => self.write_csv(w),
+        }
+    }
+
+    fn write_binary(&self, w: &mut impl Write) -> Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&0u16.to_le_bytes())?;
+        w.write_all(&self.anchor_unix_nanos.to_le_bytes())?;
+        w.write_all(&(self.records.len() as u64).to_le_bytes())?;
+        for r in self.records.iter() {
+            let (kind, code) = match r.outcome {
+                RequestOutcome::Ok => (0u32, 0u32),
+                RequestOutcome::Timeout => (1, 0),
+                RequestOutcome::Error(code) => (2, code),
+                RequestOutcome::Dropped => (3, 0),
+                RequestOutcome::Retried => (4, 0),
+                RequestOutcome::Shed => (5, 0),
+            };
+            w.write_all(&(r.id as u64).to_le_bytes())?;
+            w.write_all(&r.send_offset_ns.to_le_bytes())?;
+            w.write_all(&r.recv_offset_ns.unwrap_or(NO_RECV).to_le_bytes())?;
+            let class = u32::try_from(r.class).map_err(|_| {
+                Error::MalformedTimestamps(format!(
+                    "class {} of request {} does not fit the binary format",
+                    r.class, r.id
+                ))
+            })?;
+            w.write_all(&class.to_le_bytes())?;
+            w.write_all(&kind.to_le_bytes())?;
+            w.write_all(&code.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_csv(&self, w: &mut impl Write) -> Result<()> {
+        writeln!(w, "{}{}", CSV_ANCHOR_PREFIX, self.anchor_unix_nanos)?;
+        writeln!(w, "{}", CSV_HEADER)?;
+        for r in self.records.iter() {
+            write!(
+                w,
+                "{},{},{},{},",
+                r.id, r.class, r.outcome, r.send_offset_ns
+            )?;
+            match r.recv_offset_ns {
+                Some(o) => writeln!(w, "{}", o)?,
+                None => writeln!(w)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads either format, detected from the first bytes.
+    pub fn read(r: &mut impl Read) -> Result<Self> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        if buf.starts_with(MAGIC) {
+            Self::read_binary(&buf)
+        } else {
+            Self::read_csv(&buf[..])
+        }
+    }
+
+    fn read_binary(buf: &[u8]) -> Result<Self> {
+        const HEADER_LEN: usize = 4 + 2 + 2 + 8 + 8;
+        if buf.len() < HEADER_LEN {
+            return Err(Error::MalformedTimestamps(format!(
+                "truncated header: {} bytes",
+                buf.len()
+            )));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&buf[i..i + 4]);
+            u32::from_le_bytes(b)
+        };
+        let u64_at = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&buf[i..i + 8]);
+            u64::from_le_bytes(b)
+        };
+
+        let version = u16_at(4);
+        if version != VERSION {
+            return Err(Error::MalformedTimestamps(format!(
+                "unsupported format version: {}",
+                version
+            )));
+        }
+        let anchor_unix_nanos = u64_at(8);
+        let count = u64_at(16);
+        // the count is untrusted, so the expected length may not fit
+        match (RECORD_LEN as u64)
+            .checked_mul(count)
+            .and_then(|n| n.checked_add(HEADER_LEN as u64))
+        {
+            Some(expected) if expected == buf.len() as u64 => (),
+            Some(expected) => {
+                return Err(Error::MalformedTimestamps(format!(
+                    "{} bytes, expected {} for {} records",
+                    buf.len(),
+                    expected,
+                    count
+                )))
+            }
+            None => {
+                return Err(Error::MalformedTimestamps(format!(
+                    "{} bytes, too few for {} records",
+                    buf.len(),
+                    count
+                )))
+            }
+        }
+        let count = count as usize;
+
+        let records = (0..count)
+            .map(|n| {
+                let i = HEADER_LEN + n * RECORD_LEN;
+                let outcome = match (u32_at(i + 28), u32_at(i + 32)) {
+                    (0, _) => RequestOutcome::Ok,
+                    (1, _) => RequestOutcome::Timeout,
+                    (2, code) => RequestOutcome::Error(code),
+                    (3, _) => RequestOutcome::Dropped,
+                    (4, _) => RequestOutcome::Retried,
+                    (5, _) => RequestOutcome::Shed,
+                    (k, _) => {
+                        return Err(Error::MalformedTimestamps(format!(
+                            "record {}: unknown outcome kind {}",
+                            n, k
+                        )))
+                    }
+                };
+                Ok(TimestampRecord {
+                    id: u64_at(i) as usize,
+                    send_offset_ns: u64_at(i + 8),
+                    recv_offset_ns: match u64_at(i + 16) {
+                        NO_RECV => None,
+                        o => Some(o),
+                    },
+                    class: u32_at(i + 24) as usize,
+                    outcome,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(TimestampLog {
+            anchor_unix_nanos,
+            records,
+        })
+    }
+
+    fn read_csv(r: impl BufRead) -> Result<Self> {
+        let mut lines = r.lines();
+        let anchor_unix_nanos = match lines.next().transpose()? {
+            Some(l) => match l.strip_prefix(CSV_ANCHOR_PREFIX) {
+                Some(n) => n.trim().parse().map_err(|_| {
+                    Error::MalformedTimestamps(format!("line 1: invalid anchor `{}`", n))
+                })?,
+                None => {
+                    return Err(Error::MalformedTimestamps(format!(
+                        "line 1: expected `{}<nanos>`, got `{}`",
+                        CSV_ANCHOR_PREFIX, l
+                    )))
+                }
+            },
+            None => return Err(Error::MalformedTimestamps("empty file".to_string())),
+        };
+
+        match lines.next().transpose()? {
+            Some(l) if l.trim() == CSV_HEADER => {}
+            Some(l) => {
+                return Err(Error::MalformedTimestamps(format!(
+                    "line 2: expected header `{}`, got `{}`",
+                    CSV_HEADER, l
+                )))
+            }
+            None => return Err(Error::MalformedTimestamps("missing header".to_string())),
+        }
+
+        let mut records = vec![];
+        for (line_num, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = (|| -> std::result::Result<TimestampRecord, String> {
+                let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+                if fields.len() != 5 {
+                    return Err(format!("expected 5 fields, got {}", fields.len()));
+                }
+                Ok(TimestampRecord {
+                    id: fields[0].parse().map_err(|_| "invalid id")?,
+                    class: fields[1].parse().map_err(|_| "invalid class")?,
+                    outcome: fields[2].parse().map_err(|e: Error| e.to_string())?,
+                    send_offset_ns: fields[3].parse().map_err(|_| "invalid send offset")?,
+                    recv_offset_ns: match fields[4] {
+                        "" => None,
+                        o => Some(o.parse().map_err(|_| "invalid receive offset")?),
+                    },
+                })
+            })()
+            .map_err(|reason| {
+                Error::MalformedTimestamps(format!(
+                    "line {}: {} in `{}`",
+                    line_num + 3,
+                    reason,
+                    line
+                ))
+            })?;
+            records.push(record);
+        }
+        Ok(TimestampLog {
+            anchor_unix_nanos,
+            records,
+        })
+    }
+}
+
+/// Writes every request in `map` as absolute timestamps.
+pub fn write_timestamps(map: &LatencyMap, path: &str, format: TimestampFormat) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path).map_err(|source| Error::File {
+        path: path.to_string(),
+        source,
+    })?);
+    TimestampLog::from_latency_map(map)?.write(&mut file, format)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Reads a file written by `write_timestamps`, in either format.
+pub fn read_timestamps(path: &str) -> Result<TimestampLog> {
+    let mut file = File::open(path).map_err(|source| Error::File {
+        path: path.to_string(),
+        source,
+    })?;
+    TimestampLog::read(&mut file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> TimestampLog {
+        let record = |id, outcome, send_offset_ns, recv_offset_ns| TimestampRecord {
+            id,
+            class: id % 2,
+            outcome,
+            send_offset_ns,
+            recv_offset_ns,
+        };
+        TimestampLog {
+            anchor_unix_nanos: 1_600_000_000_000_000_000,
+            records: vec![
+                record(0, RequestOutcome::Ok, 0, Some(1_500)),
+                record(1, RequestOutcome::Timeout, 1_000, Some(90_000)),
+                record(2, RequestOutcome::Error(503), 2_000, Some(2_100)),
+                record(3, RequestOutcome::Error(7), 3_000, None),
+                record(4, RequestOutcome::Dropped, 4_000, None),
+                record(5, RequestOutcome::Retried, 5_000, None),
+                record(6, RequestOutcome::Shed, 6_000, None),
+            ],
+        }
+    }
+
+    fn written(log: &TimestampLog, format: TimestampFormat) -> Vec<u8> {
+        let mut buf = vec![];
+        log.write(&mut buf, format).unwrap();
+        buf
+    }
+
+    fn malformed(buf: &[u8]) -> String {
+        match TimestampLog::read(&mut &buf[..]) {
+            Err(Error::MalformedTimestamps(reason)) => reason,
+            other => panic!("expected malformed timestamps, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let log = log();
+        let buf = written(&log, TimestampFormat::Binary);
+        assert!(buf.starts_with(MAGIC));
+        assert_eq!(buf.len(), 24 + log.records.len() * RECORD_LEN);
+        assert_eq!(TimestampLog::read(&mut &buf[..]).unwrap(), log);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let log = log();
+        let buf = written(&log, TimestampFormat::Csv);
+        let text = String::from_utf8(buf.clone()).unwrap();
+        assert!(text.starts_with("# anchor_unix_nanos=1600000000000000000\n"));
+        assert!(text.contains("\n2,0,error:503,2000,2100\n"));
+        assert!(text.contains("\n4,0,dropped,4000,\n"));
+        assert_eq!(TimestampLog::read(&mut &buf[..]).unwrap(), log);
+    }
+
+    #[test]
+    fn latency_map_round_trip() {
+        let log = log();
+        let map = log.to_latency_map().unwrap();
+        assert_eq!(map.get(2).unwrap().class, 0);
+        assert_eq!(
+            map.get(0).unwrap().latency(),
+            Some(Duration::from_nanos(1_500))
+        );
+        assert_eq!(TimestampLog::from_latency_map(&map).unwrap(), log);
+
+        // a log anchored one second later lines up one second later
+        let reference = RunAnchor {
+            instant: Instant::now(),
+            unix_nanos: log.anchor_unix_nanos - 1_000_000_000,
+        };
+        let aligned = log.to_latency_map_aligned(&reference).unwrap();
+        assert_eq!(
+            aligned.first_send_time().unwrap() - reference.instant,
+            Duration::from_secs(1)
+        );
+
+        // and a log anchored before the reference lines up before it
+        let reference = RunAnchor {
+            instant: Instant::now(),
+            unix_nanos: log.anchor_unix_nanos + 1_000_000_000,
+        };
+        let aligned = log.to_latency_map_aligned(&reference).unwrap();
+        assert_eq!(
+            reference.instant - aligned.first_send_time().unwrap(),
+            Duration::from_secs(1)
+        );
+        assert_eq!(aligned.anchor().unwrap().unix_nanos, log.anchor_unix_nanos);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn rejects_classes_too_large_for_binary() {
+        let mut log = log();
+        log.records[3].class = u32::MAX as usize + 1;
+        let mut buf = vec![];
+        match log.write(&mut buf, TimestampFormat::Binary) {
+            Err(Error::MalformedTimestamps(reason)) => {
+                assert!(reason.contains("class"), "{}", reason)
+            }
+            other => panic!("expected malformed timestamps, got {:?}", other),
+        }
+        // CSV has no such limit
+        let buf = written(&log, TimestampFormat::Csv);
+        assert_eq!(TimestampLog::read(&mut &buf[..]).unwrap(), log);
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let buf = written(&log(), TimestampFormat::Binary);
+        assert!(malformed(&buf[..10]).contains("truncated header"));
+        assert!(malformed(&buf[..buf.len() - 1]).contains("expected"));
+        assert!(malformed(&buf[..24]).contains("expected"));
+    }
+
+    #[test]
+    fn rejects_corrupt_binary_headers() {
+        let mut buf = written(&log(), TimestampFormat::Binary);
+        buf[4] = 9;
+        assert!(malformed(&buf).contains("version"));
+
+        // a count whose length overflows is an error, not a panic
+        let mut buf = written(&log(), TimestampFormat::Binary);
+        buf[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(malformed(&buf).contains("too few"));
+
+        let mut buf = written(&log(), TimestampFormat::Binary);
+        buf[24 + 28] = 42;
+        assert!(malformed(&buf).contains("outcome kind 42"));
+    }
+
+    #[test]
+    fn rejects_malformed_csv() {
+        assert!(malformed(b"").contains("empty"));
+        assert!(malformed(b"id,class\n").contains("line 1"));
+        assert!(malformed(b"# anchor_unix_nanos=5\n").contains("missing header"));
+        let reason = malformed(b"# anchor_unix_nanos=5\nid,outcome,class,send,recv\n0,ok,0,1,2\n");
+        assert!(reason.contains("line 2"), "{}", reason);
+        assert!(reason.contains("expected header"), "{}", reason);
+        let header = "# anchor_unix_nanos=5\nid,class,outcome,send_offset_ns,recv_offset_ns\n";
+        for (line, reason) in [
+            ("0,0,ok,10", "expected 5 fields"),
+            ("x,0,ok,10,20", "invalid id"),
+            ("0,0,lost,10,20", "lost"),
+            ("0,0,ok,-1,20", "invalid send offset"),
+        ]
+        .iter()
+        {
+            let reason_found = malformed(format!("{}{}\n", header, line).as_bytes());
+            assert!(reason_found.contains("line 3"), "{}", reason_found);
+            assert!(reason_found.contains(reason), "{}", reason_found);
+        }
+    }
+}
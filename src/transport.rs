@@ -0,0 +1,470 @@
+//! Request/response transports for the load generator.
+//!
+//! A [`Transport`] opens connections, each split into a [`RequestSender`] and a
+//! [`ResponseReceiver`]. The transport embeds each request's ID in what it sends and extracts it
+//! from what comes back, so the load generator only deals in IDs and payloads.
+//!
+//! The network transports put the ID first, as a little-endian `u64`, followed by the payload.
+//! [`TcpTransport`] and [`UnixTransport`] additionally prefix each message with its length as a
+//! little-endian `u32`; messages longer than [`MAX_DATAGRAM`] are refused in both directions.
+//! The service must return the ID unchanged.
+use super::error::{Error, Result};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+const ID_LEN: usize = 8;
+/// The longest message, ID included, that a transport sends or accepts.
+pub const MAX_DATAGRAM: usize = 65536;
+
+fn too_long(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "message of {} bytes is longer than {} bytes",
+            len, MAX_DATAGRAM
+        ),
+    )
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait RequestSender: Send {
+    /// Sends request `id` with `payload`.
+    fn send<'a>(&'a mut self, id: usize, payload: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+}
+
+pub trait ResponseReceiver: Send + 'static {
+    /// Waits for the next response and returns its request ID.
+    ///
+    /// Responses the transport can't parse are skipped. An error means the connection is done.
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<usize>>;
+}
+
+pub trait Transport {
+    type Sender: RequestSender;
+    type Receiver: ResponseReceiver;
+
+    /// Opens one connection.
+    fn connect(&self) -> BoxFuture<'_, Result<(Self::Sender, Self::Receiver)>>;
+}
+
+fn encode(id: usize, payload: &[u8], framed: bool) -> Vec<u8> {
+    let len = ID_LEN + payload.len();
+    let mut msg = Vec::with_capacity(4 + len);
+    if framed {
+        msg.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    msg.extend_from_slice(&(id as u64).to_le_bytes());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+fn decode(msg: &[u8]) -> Option<usize> {
+    let mut id = [0u8; ID_LEN];
+    id.copy_from_slice(msg.get(..ID_LEN)?);
+    Some(u64::from_le_bytes(id) as usize)
+}
+
+/// Sends length-prefixed messages over one half of a stream.
+pub struct FramedSender<W>(W);
+
+impl<W: AsyncWrite + Unpin + Send> RequestSender for FramedSender<W> {
+    fn send<'a>(&'a mut self, id: usize, payload: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        if ID_LEN + payload.len() > MAX_DATAGRAM {
+            return Box::pin(futures_util::future::ready(Err(too_long(
+                ID_LEN + payload.len(),
+            ))));
+        }
+        // one write per request, so each goes out in a single segment
+        let msg = encode(id, payload, true);
+        Box::pin(async move { self.0.write_all(&msg).await })
+    }
+}
+
+/// Receives length-prefixed messages from one half of a stream.
+pub struct FramedReceiver<R> {
+    r: R,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin + Send + 'static> ResponseReceiver for FramedReceiver<R> {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<usize>> {
+        Box::pin(async move {
+            loop {
+                let mut len = [0u8; 4];
+                self.r.read_exact(&mut len).await?;
+                let len = u32::from_le_bytes(len) as usize;
+                // the length is untrusted, and past a bad one the stream can't be resynchronized
+                if len > MAX_DATAGRAM {
+                    return Err(too_long(len));
+                }
+                self.buf.resize(len, 0);
+                self.r.read_exact(&mut self.buf).await?;
+                if let Some(id) = decode(&self.buf) {
+                    return Ok(id);
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    len = self.buf.len(),
+                    "Ignoring response without a request ID"
+                );
+            }
+        })
+    }
+}
+
+fn framed<R, W>(r: R, w: W) -> (FramedSender<W>, FramedReceiver<R>) {
+    (FramedSender(w), FramedReceiver { r, buf: Vec::new() })
+}
+
+/// Length-prefixed messages over TCP.
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    pub addr: SocketAddr,
+}
+
+impl TcpTransport {
+    pub fn new(addr: SocketAddr) -> Self {
+        TcpTransport { addr }
+    }
+}
+
+impl Transport for TcpTransport {
+    type Sender = FramedSender<tokio::net::tcp::OwnedWriteHalf>;
+    type Receiver = FramedReceiver<tokio::net::tcp::OwnedReadHalf>;
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Self::Sender, Self::Receiver)>> {
+        Box::pin(async move {
+            let stream = TcpStream::connect(self.addr)
+                .await
+                .map_err(|source| Error::Connect {
+                    addr: self.addr.to_string(),
+                    source,
+                })?;
+            stream.set_nodelay(true)?;
+            let (r, w) = stream.into_split();
+            Ok(framed(r, w))
+        })
+    }
+}
+
+/// Length-prefixed messages over a Unix domain socket.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixTransport {
+    pub path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        UnixTransport { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    type Sender = FramedSender<tokio::net::unix::OwnedWriteHalf>;
+    type Receiver = FramedReceiver<tokio::net::unix::OwnedReadHalf>;
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Self::Sender, Self::Receiver)>> {
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(&self.path)
+                .await
+                .map_err(|source| Error::Connect {
+                    addr: self.path.display().to_string(),
+                    source,
+                })?;
+            let (r, w) = stream.into_split();
+            Ok(framed(r, w))
+        })
+    }
+}
+
+/// One datagram per message over UDP; each connection is its own socket.
+#[derive(Debug, Clone)]
+pub struct UdpTransport {
+    pub addr: SocketAddr,
+}
+
+impl UdpTransport {
+    pub fn new(addr: SocketAddr) -> Self {
+        UdpTransport { addr }
+    }
+}
+
+pub struct UdpSender(Arc<UdpSocket>);
+
+impl RequestSender for UdpSender {
+    fn send<'a>(&'a mut self, id: usize, payload: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        let msg = encode(id, payload, false);
+        Box::pin(async move { self.0.send(&msg).await.map(|_| ()) })
+    }
+}
+
+pub struct UdpReceiver {
+    sk: Arc<UdpSocket>,
+    buf: Vec<u8>,
+}
+
+impl ResponseReceiver for UdpReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<usize>> {
+        Box::pin(async move {
+            loop {
+                // errors such as ICMP port unreachable don't close the socket
+                let len = match self.sk.recv(&mut self.buf).await {
+                    Ok(len) => len,
+                    Err(e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(err = %e, "UDP receive failed");
+                        let _ = &e;
+                        continue;
+                    }
+                };
+                if let Some(id) = decode(&self.buf[..len]) {
+                    return Ok(id);
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!(len, "Ignoring response without a request ID");
+            }
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    type Sender = UdpSender;
+    type Receiver = UdpReceiver;
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Self::Sender, Self::Receiver)>> {
+        Box::pin(async move {
+            let bind: SocketAddr = match self.addr {
+                SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+                SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+            };
+            let sk = Arc::new(UdpSocket::bind(bind).await?);
+            sk.connect(self.addr)
+                .await
+                .map_err(|source| Error::Connect {
+                    addr: self.addr.to_string(),
+                    source,
+                })?;
+            Ok((
+                UdpSender(Arc::clone(&sk)),
+                UdpReceiver {
+                    sk,
+                    buf: vec![0u8; MAX_DATAGRAM],
+                },
+            ))
+        })
+    }
+}
+
+/// An in-process transport for testing without sockets.
+///
+/// Each request is handed to `handler` on its own task; the request gets a response when the
+/// handler returns `true`, and is lost otherwise.
+pub struct ChannelTransport<F> {
+    handler: Arc<F>,
+}
+
+impl<F, Fut> ChannelTransport<F>
+where
+    F: Fn(usize, Vec<u8>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    pub fn new(handler: F) -> Self {
+        ChannelTransport {
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+/// A [`ChannelTransport`] that responds to every request immediately.
+pub fn channel_echo(
+) -> ChannelTransport<impl Fn(usize, Vec<u8>) -> futures_util::future::Ready<bool>> {
+    ChannelTransport::new(|_, _| futures_util::future::ready(true))
+}
+
+pub struct ChannelSender<F> {
+    handler: Arc<F>,
+    responses: mpsc::UnboundedSender<usize>,
+}
+
+impl<F, Fut> RequestSender for ChannelSender<F>
+where
+    F: Fn(usize, Vec<u8>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    fn send<'a>(&'a mut self, id: usize, payload: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        let respond = (self.handler)(id, payload.to_vec());
+        let responses = self.responses.clone();
+        tokio::spawn(async move {
+            if respond.await {
+                // the receiver is gone once the run is over
+                let _ = responses.send(id);
+            }
+        });
+        Box::pin(futures_util::future::ready(Ok(())))
+    }
+}
+
+pub struct ChannelReceiver(mpsc::UnboundedReceiver<usize>);
+
+impl ResponseReceiver for ChannelReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<usize>> {
+        Box::pin(async move {
+            self.0
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Sender closed"))
+        })
+    }
+}
+
+impl<F, Fut> Transport for ChannelTransport<F>
+where
+    F: Fn(usize, Vec<u8>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    type Sender = ChannelSender<F>;
+    type Receiver = ChannelReceiver;
+
+    fn connect(&self) -> BoxFuture<'_, Result<(Self::Sender, Self::Receiver)>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let sender = ChannelSender {
+            handler: Arc::clone(&self.handler),
+            responses: tx,
+        };
+        Box::pin(futures_util::future::ready(Ok((
+            sender,
+            ChannelReceiver(rx),
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadgen::{EchoServer, Protocol};
+    use std::time::Duration;
+
+    fn localhost() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[test]
+    fn encodes_the_id_first() {
+        let msg = encode(7, b"abc", false);
+        assert_eq!(&msg[..8], &7u64.to_le_bytes());
+        assert_eq!(&msg[8..], b"abc");
+        assert_eq!(decode(&msg), Some(7));
+
+        let framed = encode(7, b"abc", true);
+        assert_eq!(&framed[..4], &11u32.to_le_bytes());
+        assert_eq!(&framed[4..], &msg[..]);
+
+        assert_eq!(decode(&msg[..7]), None);
+    }
+
+    #[tokio::test]
+    async fn framed_round_trip() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (mut sender, _) = framed(tokio::io::empty(), client);
+        let (_, mut receiver) = framed(server, tokio::io::sink());
+        sender.send(1, b"hello").await.unwrap();
+        sender.send(2, b"").await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap(), 1);
+        assert_eq!(receiver.recv().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn skips_frames_without_an_id() {
+        let mut stream = vec![];
+        stream.extend_from_slice(&3u32.to_le_bytes());
+        stream.extend_from_slice(b"abc");
+        stream.extend_from_slice(&encode(9, b"", true));
+        let (_, mut receiver) = framed(io::Cursor::new(stream), tokio::io::sink());
+        assert_eq!(receiver.recv().await.unwrap(), 9);
+        // then the stream ends
+        assert!(receiver.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frames() {
+        let mut stream = vec![];
+        stream.extend_from_slice(&u32::MAX.to_le_bytes());
+        stream.extend_from_slice(&encode(1, b"", false));
+        let (_, mut receiver) = framed(io::Cursor::new(stream), tokio::io::sink());
+        let err = receiver.recv().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let (mut sender, _) = framed(tokio::io::empty(), tokio::io::sink());
+        let payload = vec![0u8; MAX_DATAGRAM];
+        let err = sender.send(1, &payload).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        sender
+            .send(1, &payload[..MAX_DATAGRAM - ID_LEN])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_and_udp_echo() {
+        let tcp = EchoServer::bind(Protocol::Tcp, localhost()).await.unwrap();
+        let (mut sender, mut receiver) =
+            TcpTransport::new(tcp.local_addr()).connect().await.unwrap();
+        sender.send(42, b"payload").await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap(), 42);
+
+        let udp = EchoServer::bind(Protocol::Udp, localhost()).await.unwrap();
+        let (mut sender, mut receiver) =
+            UdpTransport::new(udp.local_addr()).connect().await.unwrap();
+        sender.send(43, b"payload").await.unwrap();
+        let id = tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, 43);
+    }
+
+    #[tokio::test]
+    async fn connect_errors_name_the_address() {
+        // bind and drop a listener to find a port nothing listens on
+        let addr = std::net::TcpListener::bind(localhost())
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        match TcpTransport::new(addr).connect().await {
+            Err(Error::Connect { addr: a, .. }) => assert_eq!(a, addr.to_string()),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("connected to a closed port"),
+        }
+    }
+
+    #[tokio::test]
+    async fn channel_transport_responds_per_handler() {
+        let transport =
+            ChannelTransport::new(|id, payload: Vec<u8>| async move { id != 2 && payload == b"x" });
+        let (mut sender, mut receiver) = transport.connect().await.unwrap();
+        for id in 0..4 {
+            sender.send(id, b"x").await.unwrap();
+        }
+        sender.send(4, b"y").await.unwrap();
+        let mut ids = vec![];
+        for _ in 0..3 {
+            ids.push(receiver.recv().await.unwrap());
+        }
+        ids.sort();
+        assert_eq!(ids, [0, 1, 3]);
+
+        // the receiver ends once every sender is gone
+        drop(sender);
+        assert!(receiver.recv().await.is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Aggregation of repeated trials of the same configuration.
+use super::error::{Error, Result};
+use super::stats::{mean, median, stddev, student_t_quantile};
+use super::summary_stats::SummaryStats;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_json::to_writer;
+#[cfg(feature = "serde")]
+use std::fs::File;
+
+// Trials whose modified z-score exceeds this are flagged as outliers (Iglewicz and Hoaglin).
+const OUTLIER_THRESHOLD: f64 = 3.5;
+
+/// Spread of one metric across trials.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrialStat {
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    // confidence interval of the mean, using Student's t distribution
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl TrialStat {
+    fn new(values: &[f64], confidence: f64) -> Self {
+        let m = mean(values);
+        let sd = stddev(values);
+        let half_width = if values.len() > 1 {
+            let t = student_t_quantile(1.0 - (1.0 - confidence) / 2.0, values.len() - 1);
+            t * sd / (values.len() as f64).sqrt()
+        } else {
+            0.0
+        };
+        TrialStat {
+            mean: m,
+            median: median(values),
+            stddev: sd,
+            min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+            max: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            ci_low: m - half_width,
+            ci_high: m + half_width,
+        }
+    }
+}
+
+/// Latency at one quantile across trials, in nanoseconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuantileTrialStat {
+    pub quantile: f64,
+    pub stat: TrialStat,
+}
+
+/// A trial whose results lie far from the others.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OutlierTrial {
+    // index of the trial in the input
+    pub trial: usize,
+    // the metrics on which the trial is an outlier, e.g. "p99" or "throughput"
+    pub metrics: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrialAggregate {
+    pub num_trials: usize,
+    pub confidence: f64,
+    pub quantiles: Vec<QuantileTrialStat>,
+    // successful responses per second
+    pub throughput: TrialStat,
+    // requests sent per second
+    pub offered_load: TrialStat,
+    pub outliers: Vec<OutlierTrial>,
+}
+
+impl TrialAggregate {
+    /// Aggregates trials with 95% confidence intervals.
+    pub fn new(trials: &[SummaryStats], quantiles: &[f64]) -> Result<Self> {
+        Self::with_confidence(trials, quantiles, 0.95)
+    }
+
+    pub fn with_confidence(
+        trials: &[SummaryStats],
+        quantiles: &[f64],
+        confidence: f64,
+    ) -> Result<Self> {
+        if trials.is_empty() {
+            return Err(Error::NothingToMerge);
+        }
+        if !(confidence > 0.0 && confidence < 1.0) {
+            return Err(Error::InvalidArgument(format!(
+                "Confidence must be in (0, 1): {}",
+                confidence
+            )));
+        }
+
+        let mut metrics: Vec<(String, Vec<f64>)> = Vec::with_capacity(quantiles.len() + 2);
+        for q in quantiles {
+            let values = trials
+                .iter()
+                .map(|t| t.histogram.value_at_quantile(*q).map(|v| v as f64))
+                .collect::<Result<Vec<_>>>()?;
+            metrics.push((quantile_name(*q), values));
+        }
+        let throughput: Vec<f64> = trials.iter().map(SummaryStats::throughput).collect();
+        let offered_load: Vec<f64> = trials.iter().map(SummaryStats::offered_load).collect();
+        metrics.push(("throughput".to_string(), throughput.clone()));
+
+        let mut outliers: Vec<OutlierTrial> = Vec::new();
+        for (name, values) in metrics.iter() {
+            for trial in outlier_indices(values) {
+                match outliers.iter_mut().find(|o| o.trial == trial) {
+                    Some(o) => o.metrics.push(name.clone()),
+                    None => outliers.push(OutlierTrial {
+                        trial,
+                        metrics: vec![name.clone()],
+                    }),
+                }
+            }
+        }
+        outliers.sort_by_key(|o| o.trial);
+        #[cfg(feature = "tracing")]
+        for o in outliers.iter() {
+            tracing::warn!(trial = o.trial, metrics = ?o.metrics, "Outlier trial");
+        }
+
+        Ok(TrialAggregate {
+            num_trials: trials.len(),
+            confidence,
+            quantiles: quantiles
+                .iter()
+                .zip(metrics.iter())
+                .map(|(q, (_, values))| QuantileTrialStat {
+                    quantile: *q,
+                    stat: TrialStat::new(values, confidence),
+                })
+                .collect(),
+            throughput: TrialStat::new(&throughput, confidence),
+            offered_load: TrialStat::new(&offered_load, confidence),
+            outliers,
+        })
+    }
+
+    pub fn quantile(&self, quantile: f64) -> Option<&TrialStat> {
+        self.quantiles
+            .iter()
+            .find(|q| (q.quantile - quantile).abs() < f64::EPSILON)
+            .map(|q| &q.stat)
+    }
+}
+
+// e.g. 0.99 -> "p99", 0.999 -> "p99.9"
+fn quantile_name(quantile: f64) -> String {
+    format!("p{}", (quantile * 1e6).round() / 1e4)
+}
+
+// Flags values whose modified z-score, based on the median absolute deviation, is too large.
+fn outlier_indices(values: &[f64]) -> Vec<usize> {
+    let med = median(values);
+    let deviations: Vec<f64> = values.iter().map(|v| (v - med).abs()).collect();
+    let mad = median(&deviations);
+    if mad == 0.0 {
+        return Vec::new();
+    }
+    deviations
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| 0.6745 * **d / mad > OUTLIER_THRESHOLD)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+pub fn write_to_file(aggregate: &TrialAggregate, path: String) -> Result<()> {
+    let file = File::create(&path).map_err(|source| Error::File {
+        path: path.clone(),
+        source,
+    })?;
+    to_writer(&file, aggregate).map_err(|source| Error::Json {
+        path: Some(path),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary_stats::test_stats;
+
+    // a one-second trial of 100 requests, `received` of which were answered after `latency_ns`
+    fn trial(latency_ns: u64, received: usize) -> SummaryStats {
+        test_stats(100, &vec![latency_ns; received])
+    }
+
+    #[test]
+    fn aggregates_each_metric() {
+        let trials = [trial(100, 90), trial(200, 100), trial(300, 110)];
+        let agg = TrialAggregate::new(&trials, &[0.5, 0.99]).unwrap();
+        assert_eq!(agg.num_trials, 3);
+
+        let p50 = agg.quantile(0.5).unwrap();
+        assert_eq!(
+            (p50.mean, p50.median, p50.min, p50.max),
+            (200.0, 200.0, 100.0, 300.0)
+        );
+        assert!((p50.stddev - 100.0).abs() < 1e-9);
+        // t(0.975, 2) = 4.303
+        let half_width = 4.303 * 100.0 / 3f64.sqrt();
+        assert!((p50.ci_high - p50.mean - half_width).abs() < 0.5);
+        assert!((p50.mean - p50.ci_low - half_width).abs() < 0.5);
+
+        assert_eq!(agg.throughput.mean, 100.0);
+        assert_eq!(agg.offered_load.stddev, 0.0);
+        assert!(agg.outliers.is_empty());
+    }
+
+    #[test]
+    fn single_trial_has_no_spread() {
+        let agg = TrialAggregate::new(&[trial(100, 100)], &[0.5]).unwrap();
+        let p50 = agg.quantile(0.5).unwrap();
+        assert_eq!((p50.ci_low, p50.ci_high), (100.0, 100.0));
+    }
+
+    #[test]
+    fn flags_outlier_trials() {
+        let mut trials: Vec<_> = [100, 101, 99, 100, 102, 98, 100, 101, 99]
+            .iter()
+            .map(|l| trial(*l, 100))
+            .collect();
+        trials.push(trial(1000, 100));
+        let agg = TrialAggregate::new(&trials, &[0.5, 0.999]).unwrap();
+        assert_eq!(
+            agg.outliers,
+            [OutlierTrial {
+                trial: 9,
+                metrics: vec!["p50".to_string(), "p99.9".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(matches!(
+            TrialAggregate::new(&[], &[0.5]),
+            Err(Error::NothingToMerge)
+        ));
+        assert!(TrialAggregate::with_confidence(&[trial(100, 100)], &[0.5], 1.0).is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Closed-loop and partly-open workloads.
+//!
+//! In a closed loop, a fixed number of clients each send a request, wait for the response, and
+//! think for a while before sending the next, so the offered load falls as the server slows
+//! down. In a partly-open workload, sessions arrive open-loop on a schedule, and each session
+//! sends a fixed number of requests closed-loop. Both record into a [`LatencyMap`] the same way
+//! the open-loop drivers do, so results can be compared directly.
+use super::error::{Error, Result};
+use super::histogram::{LatencyMap, RequestOutcome};
+use super::requests::{DistributionType, RequestSchedule};
+use super::summary_stats::SummaryStats;
+use super::SpinTicker;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, Exp};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How long a client waits after a response before sending its next request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThinkTime {
+    Zero,
+    Fixed(Duration),
+    // exponentially distributed with the given mean
+    Exponential(Duration),
+}
+
+impl ThinkTime {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            ThinkTime::Zero => Duration::from_secs(0),
+            ThinkTime::Fixed(d) => d,
+            ThinkTime::Exponential(mean) if mean.as_nanos() == 0 => mean,
+            ThinkTime::Exponential(mean) => {
+                let exp = Exp::new(1.0 / mean.as_nanos() as f64)
+                    .expect("Not able to make exponential distribution");
+                Duration::from_nanos(exp.sample(rng) as u64)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClosedLoopConfig {
+    pub clients: usize,
+    // each client stops after this many requests; None to run for the whole duration
+    pub requests_per_client: Option<usize>,
+    pub think_time: ThinkTime,
+    // clients stop sending after this long
+    pub duration: Duration,
+    // how long to wait for outstanding responses after the duration;
+    // requests still outstanding afterwards are counted as dropped
+    pub drain: Duration,
+    // requests slower than this are abandoned and counted as timeouts
+    pub timeout: Option<Duration>,
+    pub warmup: Duration,
+    pub cooldown: Duration,
+    pub histogram_precision: Option<u64>,
+    // client i samples think times with seed + i
+    pub seed: u64,
+}
+
+impl Default for ClosedLoopConfig {
+    fn default() -> Self {
+        ClosedLoopConfig {
+            clients: 1,
+            requests_per_client: None,
+            think_time: ThinkTime::Zero,
+            duration: Duration::from_secs(10),
+            drain: Duration::from_secs(1),
+            timeout: None,
+            warmup: Duration::from_secs(1),
+            cooldown: Duration::from_secs(1),
+            histogram_precision: None,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartlyOpenConfig {
+    // sessions per second
+    pub session_rate: f64,
+    pub session_distribution: DistributionType,
+    pub requests_per_session: usize,
+    pub think_time: ThinkTime,
+    // sessions arrive for this long
+    pub duration: Duration,
+    // how long to wait for sessions to finish after the last arrival;
+    // requests still outstanding afterwards are counted as dropped
+    pub drain: Duration,
+    // requests slower than this are abandoned and counted as timeouts
+    pub timeout: Option<Duration>,
+    pub warmup: Duration,
+    pub cooldown: Duration,
+    pub histogram_precision: Option<u64>,
+    // seeds the session arrivals; session i samples think times with seed + 1 + i, so no session
+    // shares the arrivals' stream
+    pub seed: u64,
+}
+
+impl Default for PartlyOpenConfig {
+    fn default() -> Self {
+        PartlyOpenConfig {
+            session_rate: 100.0,
+            session_distribution: DistributionType::Exponential,
+            requests_per_session: 10,
+            think_time: ThinkTime::Zero,
+            duration: Duration::from_secs(10),
+            drain: Duration::from_secs(1),
+            timeout: None,
+            warmup: Duration::from_secs(1),
+            cooldown: Duration::from_secs(1),
+            histogram_precision: None,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkloadResult {
+    pub latencies: LatencyMap,
+    pub stats: SummaryStats,
+}
+
+// State shared by every client or session of one run.
+struct Shared<F> {
+    request: F,
+    latencies: Mutex<LatencyMap>,
+    // request IDs are assigned in send order across all clients
+    next_id: AtomicUsize,
+    timeout: Option<Duration>,
+    think_time: ThinkTime,
+}
+
+impl<F, Fut> Shared<F>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = RequestOutcome>,
+{
+    fn record(
+        &self,
+        id: usize,
+        sent: Instant,
+        end: Option<Instant>,
+        outcome: RequestOutcome,
+    ) -> Result<()> {
+        self.latencies
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .record_outcome(id, sent, end, outcome)
+    }
+
+    /// Sends requests one after another until `max_requests` are sent or `deadline` passes.
+    async fn run_client(
+        &self,
+        max_requests: Option<usize>,
+        deadline: Option<Instant>,
+        seed: u64,
+    ) -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut sent_count = 0;
+        while max_requests.is_none_or(|m| sent_count < m)
+            && deadline.is_none_or(|d| Instant::now() < d)
+        {
+            let id = self.next_id.fetch_add(1, Ordering::AcqRel);
+            let sent = Instant::now();
+            // in-flight requests stay dropped if the run is cut off
+            self.record(id, sent, None, RequestOutcome::Dropped)?;
+            let outcome = match self.timeout {
+                Some(t) => tokio::time::timeout(t, (self.request)(id))
+                    .await
+                    .unwrap_or(RequestOutcome::Timeout),
+                None => (self.request)(id).await,
+            };
+            let end = match outcome {
+                RequestOutcome::Dropped | RequestOutcome::Retried | RequestOutcome::Shed => None,
+                _ => Some(Instant::now()),
+            };
+            self.record(id, sent, end, outcome)?;
+            sent_count += 1;
+            if max_requests == Some(sent_count) {
+                break;
+            }
+
+            let think = self.think_time.sample(&mut rng);
+            if think > Duration::from_secs(0) {
+                tokio::time::sleep(think).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn drain(mut handles: Vec<JoinHandle<Result<()>>>, drain: Duration) -> Result<()> {
+    let drained = tokio::time::timeout(drain, async {
+        for handle in handles.iter_mut() {
+            match handle.await {
+                Ok(r) => r?,
+                Err(e) if e.is_cancelled() => (),
+                Err(e) => return Err(Error::TaskFailed(e.to_string())),
+            }
+        }
+        Ok(())
+    })
+    .await;
+    if drained.is_err() {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("Requests still outstanding after drain period");
+    }
+    for handle in handles {
+        handle.abort();
+    }
+    drained.unwrap_or(Ok(()))
+}
+
+fn finish<F>(
+    shared: &Shared<F>,
+    warmup: Duration,
+    cooldown: Duration,
+    histogram_precision: Option<u64>,
+) -> Result<WorkloadResult> {
+    let latencies = std::mem::take(&mut *shared.latencies.lock().map_err(|_| Error::LockPoisoned)?);
+    let stats = SummaryStats::new(&latencies, warmup, cooldown, true, histogram_precision)?;
+    Ok(WorkloadResult { latencies, stats })
+}
+
+fn new_latency_map(timeout: Option<Duration>) -> LatencyMap {
+    match timeout {
+        Some(t) => LatencyMap::with_timeout(t),
+        None => LatencyMap::new(),
+    }
+}
+
+/// Runs `config.clients` closed-loop clients, each calling `request` with a fresh request ID
+/// and waiting for it to finish before thinking and sending the next.
+pub async fn run_closed_loop<F, Fut>(
+    config: &ClosedLoopConfig,
+    request: F,
+) -> Result<WorkloadResult>
+where
+    F: Fn(usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = RequestOutcome> + Send + 'static,
+{
+    if config.clients == 0 {
+        return Err(Error::InvalidArgument(
+            "Closed-loop workload needs at least one client".to_string(),
+        ));
+    }
+
+    let shared = Arc::new(Shared {
+        request,
+        latencies: Mutex::new(new_latency_map(config.timeout)),
+        next_id: AtomicUsize::new(0),
+        timeout: config.timeout,
+        think_time: config.think_time,
+    });
+    let deadline = Instant::now() + config.duration;
+    let handles = (0..config.clients)
+        .map(|i| {
+            let shared = Arc::clone(&shared);
+            let max_requests = config.requests_per_client;
+            let seed = config.seed.wrapping_add(i as u64);
+            tokio::spawn(async move { shared.run_client(max_requests, Some(deadline), seed).await })
+        })
+        .collect();
+
+    drain(handles, config.duration + config.drain).await?;
+    finish(
+        &shared,
+        config.warmup,
+        config.cooldown,
+        config.histogram_precision,
+    )
+}
+
+/// Starts sessions on a schedule at `config.session_rate`; each session sends
+/// `config.requests_per_session` requests closed-loop.
+pub async fn run_partly_open<F, Fut>(
+    config: &PartlyOpenConfig,
+    request: F,
+) -> Result<WorkloadResult>
+where
+    F: Fn(usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = RequestOutcome> + Send + 'static,
+{
+    if config.requests_per_session == 0 {
+        return Err(Error::InvalidArgument(
+            "Sessions must send at least one request".to_string(),
+        ));
+    }
+
+    let num_sessions = (config.session_rate * config.duration.as_secs_f64()).ceil() as usize;
+    let schedule = RequestSchedule::new_seeded(
+        num_sessions,
+        config.session_rate,
+        config.session_distribution,
+        config.seed,
+    )?;
+    let shared = Arc::new(Shared {
+        request,
+        latencies: Mutex::new(new_latency_map(config.timeout)),
+        next_id: AtomicUsize::new(0),
+        timeout: config.timeout,
+        think_time: config.think_time,
+    });
+
+    let mut ticker = SpinTicker::new(schedule, config.duration);
+    let mut handles = Vec::with_capacity(num_sessions);
+    while (&mut ticker).await.is_some() {
+        let shared = Arc::clone(&shared);
+        let requests = config.requests_per_session;
+        let seed = config.seed.wrapping_add(1 + handles.len() as u64);
+        handles.push(tokio::spawn(async move {
+            shared.run_client(Some(requests), None, seed).await
+        }));
+    }
+
+    drain(handles, config.drain).await?;
+    finish(
+        &shared,
+        config.warmup,
+        config.cooldown,
+        config.histogram_precision,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed_loop(clients: usize, requests_per_client: Option<usize>) -> ClosedLoopConfig {
+        ClosedLoopConfig {
+            clients,
+            requests_per_client,
+            duration: Duration::from_millis(200),
+            drain: Duration::from_millis(200),
+            warmup: Duration::from_secs(0),
+            cooldown: Duration::from_secs(0),
+            ..Default::default()
+        }
+    }
+
+    fn partly_open(requests_per_session: usize) -> PartlyOpenConfig {
+        PartlyOpenConfig {
+            session_rate: 100.0,
+            session_distribution: DistributionType::Uniform,
+            requests_per_session,
+            duration: Duration::from_millis(100),
+            drain: Duration::from_millis(500),
+            warmup: Duration::from_secs(0),
+            cooldown: Duration::from_secs(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn think_times() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let ms = Duration::from_millis(1);
+        assert_eq!(ThinkTime::Zero.sample(&mut rng), Duration::from_secs(0));
+        assert_eq!(ThinkTime::Fixed(ms).sample(&mut rng), ms);
+        assert_eq!(
+            ThinkTime::Exponential(Duration::from_secs(0)).sample(&mut rng),
+            Duration::from_secs(0)
+        );
+        let total: Duration = (0..10_000)
+            .map(|_| ThinkTime::Exponential(ms).sample(&mut rng))
+            .sum();
+        let mean = total.as_secs_f64() / 10_000.0;
+        assert!((mean - 1e-3).abs() < 1e-4, "mean {}", mean);
+    }
+
+    #[tokio::test]
+    async fn each_client_sends_its_requests() {
+        let result = run_closed_loop(&closed_loop(3, Some(4)), |_| async { RequestOutcome::Ok })
+            .await
+            .unwrap();
+        assert_eq!(result.latencies.len(), 12);
+        assert_eq!(result.stats.outcomes.ok, 12);
+        // IDs are assigned in send order across clients
+        assert!((0..12).all(|id| result.latencies.get(id).is_some()));
+    }
+
+    #[tokio::test]
+    async fn clients_wait_for_each_response() {
+        // one client with 20ms requests can't send more than ~10 in 200ms
+        let result = run_closed_loop(&closed_loop(1, None), |_| async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            RequestOutcome::Ok
+        })
+        .await
+        .unwrap();
+        let sent = result.latencies.len();
+        assert!((5..=11).contains(&sent), "sent {}", sent);
+        assert!(result.stats.histogram.count > 0);
+        assert!(result.stats.histogram.value_at_quantile(0.5).unwrap() >= 20_000_000);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_time_out() {
+        let config = ClosedLoopConfig {
+            timeout: Some(Duration::from_millis(5)),
+            ..closed_loop(2, Some(2))
+        };
+        let result = run_closed_loop(&config, |_| async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            RequestOutcome::Ok
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.stats.outcomes.timeout, 4);
+        assert_eq!(result.stats.outcomes.ok, 0);
+    }
+
+    #[tokio::test]
+    async fn records_outcomes_without_responses() {
+        let result = run_closed_loop(&closed_loop(1, Some(4)), |id| async move {
+            match id % 2 {
+                0 => RequestOutcome::Error(500),
+                _ => RequestOutcome::Dropped,
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.stats.outcomes.errors.get(&500), Some(&2));
+        assert_eq!(result.stats.outcomes.dropped, 2);
+        assert!(result.latencies.get(1).unwrap().latency().is_none());
+    }
+
+    #[tokio::test]
+    async fn sessions_send_their_requests() {
+        let result = run_partly_open(&partly_open(3), |_| async { RequestOutcome::Ok })
+            .await
+            .unwrap();
+        // 100 sessions/s for 100ms
+        assert_eq!(result.latencies.len(), 30);
+        assert_eq!(result.stats.outcomes.ok, 30);
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_workloads() {
+        assert!(matches!(
+            run_closed_loop(&closed_loop(0, None), |_| async { RequestOutcome::Ok }).await,
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            run_partly_open(&partly_open(0), |_| async { RequestOutcome::Ok }).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}